@@ -0,0 +1,310 @@
+//! Address points and address geocoding.
+//!
+//! Addresses come from the `addr:housenumber` tag together with `addr:street`, or
+//! `addr:place` for addresses without a street, of nodes and of ways such as building
+//! outlines. They are read from the tags kept at import, so [`ImportOptions`] that drop
+//! `addr:*` tags leave a file without addresses.
+//!
+//! [`ImportOptions`]: crate::ImportOptions
+
+use std::collections::HashMap;
+
+use kdtree::KdTree;
+
+use crate::search::normalize;
+use crate::{check_coordinate, dist_haversine, MemberType, TOSMFile, Tags, TosmError};
+
+/// A house number on a street, at a node or at the centre of a way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address<'a> {
+    pub street: &'a str,
+    pub housenumber: &'a str,
+    /// Whether the address is tagged on a node or on a way.
+    pub element_type: MemberType,
+    pub element_id: u64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Result of [`TOSMFile::reverse_geocode_address`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressDistance<'a> {
+    pub address: Address<'a>,
+    pub distance_m: f64,
+}
+
+/// The address points of a file, by street and house number and by location.
+#[derive(Debug)]
+pub(crate) struct AddressIndex {
+    points: Vec<Point>,
+    /// Point indexes by normalized street name and house number.
+    by_street: HashMap<(String, String), Vec<u32>>,
+    tree: KdTree<f64, u32, [f64; 2]>,
+}
+
+#[derive(Debug)]
+struct Point {
+    element_type: MemberType,
+    id: u64,
+    location: [f64; 2],
+}
+
+impl Default for AddressIndex {
+    fn default() -> Self {
+        AddressIndex {
+            points: vec![],
+            by_street: HashMap::new(),
+            tree: KdTree::new(2),
+        }
+    }
+}
+
+impl AddressIndex {
+    pub fn new(file: &TOSMFile) -> Self {
+        // Points with their normalized street name and house number.
+        let mut points = vec![];
+        for (id, tags) in file.tags.tagged_nodes() {
+            if let (Some(node), Some(key)) = (file.node(id), parts(tags)) {
+                let point = Point {
+                    element_type: MemberType::Node,
+                    id,
+                    location: [node.lat(), node.lon()],
+                };
+                points.push((point, key));
+            }
+        }
+        for (id, tags) in file.tags.tagged_ways() {
+            let location = file.way(id).and_then(|way| centre(&file.way_coords(way)));
+            if let (Some(location), Some(key)) = (location, parts(tags)) {
+                let point = Point {
+                    element_type: MemberType::Way,
+                    id,
+                    location,
+                };
+                points.push((point, key));
+            }
+        }
+        points.sort_by_key(|(p, _)| (p.element_type != MemberType::Node, p.id));
+
+        let mut index = AddressIndex::default();
+        for (i, (point, (street, housenumber))) in points.into_iter().enumerate() {
+            index
+                .by_street
+                .entry((normalize(street), normalize_housenumber(housenumber)))
+                .or_default()
+                .push(i as u32);
+            // Locations come from checked node coordinates, which the tree accepts.
+            let _ = index.tree.add(point.location, i as u32);
+            index.points.push(point);
+        }
+        index
+    }
+}
+
+impl TOSMFile {
+    /// Finds the address points matching a query such as `"Fjólugata 5"` or `"5 Fjólugata"`.
+    /// The street name is compared accent-folded and case-insensitively, and anything after a
+    /// comma, such as a town, is ignored. Returns every match, as the same address can exist
+    /// in several towns.
+    pub fn geocode_address(&self, query: &str) -> Vec<Address<'_>> {
+        let Some(key) = parse_query(query) else {
+            return vec![];
+        };
+        let index = self.address_index();
+        index
+            .by_street
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter_map(|&i| self.address(&index.points[i as usize]))
+            .collect()
+    }
+
+    /// Returns the address point closest to a coordinate, such as the house a courier is
+    /// standing at, if one lies within `max_distance_m`.
+    pub fn reverse_geocode_address(
+        &self,
+        lat: f64,
+        lon: f64,
+        max_distance_m: f64,
+    ) -> Result<Option<AddressDistance<'_>>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let index = self.address_index();
+        let nearest = index
+            .tree
+            .nearest(&[lat, lon], 1, &dist_haversine)
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        Ok(nearest.first().and_then(|&(dist_km, &i)| {
+            let distance_m = dist_km * 1000.0;
+            if distance_m > max_distance_m {
+                return None;
+            }
+            Some(AddressDistance {
+                address: self.address(&index.points[i as usize])?,
+                distance_m,
+            })
+        }))
+    }
+
+    fn address(&self, point: &Point) -> Option<Address<'_>> {
+        let (street, housenumber) = parts(index_tags(self, point))?;
+        Some(Address {
+            street,
+            housenumber,
+            element_type: point.element_type,
+            element_id: point.id,
+            lat: point.location[0],
+            lon: point.location[1],
+        })
+    }
+}
+
+fn index_tags<'a>(file: &'a TOSMFile, point: &Point) -> Tags<'a> {
+    match point.element_type {
+        MemberType::Way => file.tags.way(point.id),
+        _ => file.tags.node(point.id),
+    }
+}
+
+/// The street, or place, and house number of an element's tags.
+fn parts(tags: Tags<'_>) -> Option<(&str, &str)> {
+    let street = tags.get("addr:street").or_else(|| tags.get("addr:place"))?;
+    Some((street, tags.get("addr:housenumber")?))
+}
+
+/// Splits a query into the normalized street name and house number. The house number is the
+/// last or the first word, whichever starts with a digit. A single letter after a trailing
+/// house number belongs to it, as in `Fjólugata 7 A`.
+fn parse_query(query: &str) -> Option<(String, String)> {
+    let query = query.split(',').next()?;
+    let words: Vec<&str> = query.split_whitespace().collect();
+    let is_number = |word: &&str| word.starts_with(|c: char| c.is_ascii_digit());
+    let is_letter = |word: &&str| {
+        let mut chars = word.chars();
+        chars.next().is_some_and(char::is_alphabetic) && chars.next().is_none()
+    };
+
+    let (street, housenumber) = match words.as_slice() {
+        [street @ .., number, letter]
+            if !street.is_empty() && is_number(number) && is_letter(letter) =>
+        {
+            (street, [*number, *letter].concat())
+        }
+        [street @ .., last] if !street.is_empty() && is_number(last) => (street, last.to_string()),
+        [first, street @ ..] if !street.is_empty() && is_number(first) => {
+            (street, first.to_string())
+        }
+        _ => return None,
+    };
+    Some((
+        normalize(&street.join(" ")),
+        normalize_housenumber(&housenumber),
+    ))
+}
+
+/// Lowercases a house number and removes its spaces, so `5 A` and `5a` are the same.
+fn normalize_housenumber(housenumber: &str) -> String {
+    housenumber
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The mean of the distinct points of a way, leaving out the repeated first node of a closed
+/// way.
+fn centre(coords: &[[f64; 2]]) -> Option<[f64; 2]> {
+    let coords = match coords {
+        [first, rest @ .., last] if first == last && !rest.is_empty() => {
+            &coords[..coords.len() - 1]
+        }
+        _ => coords,
+    };
+    if coords.is_empty() {
+        return None;
+    }
+    let n = coords.len() as f64;
+    let lat = coords.iter().map(|c| c[0]).sum::<f64>() / n;
+    let lon = coords.iter().map(|c| c[1]).sum::<f64>() / n;
+    Some([lat, lon])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ImportOptions, MemberType, TOSMFile};
+
+    const SOURCE: &str = r#"{
+        "nodes": [
+            {"id": 1, "lat": 64.1420, "lon": -21.9390},
+            {"id": 2, "lat": 64.1420, "lon": -21.9370},
+            {"id": 3, "lat": 64.1421, "lon": -21.9385,
+                "tags": {"addr:street": "Fjólugata", "addr:housenumber": "5"}},
+            {"id": 4, "lat": 64.1422, "lon": -21.9378},
+            {"id": 5, "lat": 64.1422, "lon": -21.9376},
+            {"id": 6, "lat": 64.1424, "lon": -21.9376},
+            {"id": 7, "lat": 64.1424, "lon": -21.9378},
+            {"id": 8, "lat": 65.6800, "lon": -18.1000,
+                "tags": {"addr:street": "Fjólugata", "addr:housenumber": "5"}}
+        ],
+        "ways": [
+            {"id": 10, "node_ids": [1, 2], "one_way": false, "name": "Fjólugata"},
+            {"id": 11, "node_ids": [4, 5, 6, 7, 4], "one_way": false, "name": null,
+                "tags": {"building": "house", "addr:street": "Fjólugata",
+                    "addr:housenumber": "7 A"}}
+        ]
+    }"#;
+
+    #[test]
+    fn geocodes_addresses() {
+        let file = TOSMFile::from_json_str(SOURCE).unwrap();
+
+        let found = file.geocode_address("Fjolugata 5, Reykjavík");
+        let ids: Vec<u64> = found.iter().map(|a| a.element_id).collect();
+        assert_eq!(ids, [3, 8]);
+        assert_eq!(found[0].street, "Fjólugata");
+        assert_eq!(found[0].housenumber, "5");
+
+        let found = file.geocode_address("7a fjólugata");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].element_type, MemberType::Way);
+        assert!((found[0].lat - 64.1423).abs() < 1e-9);
+        assert!((found[0].lon + 21.9377).abs() < 1e-9);
+
+        for query in ["Fjólugata 7 A", "Fjólugata 7a", "fjolugata 7A, Reykjavík"] {
+            let ids: Vec<u64> = file
+                .geocode_address(query)
+                .iter()
+                .map(|a| a.element_id)
+                .collect();
+            assert_eq!(ids, [11], "{query}");
+        }
+        assert!(file.geocode_address("Fjólugata 7 B").is_empty());
+
+        assert!(file.geocode_address("Fjólugata 9").is_empty());
+        assert!(file.geocode_address("Fjólugata").is_empty());
+
+        let nearest = file
+            .reverse_geocode_address(64.1425, -21.9377, 50.0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(nearest.address.housenumber, "7 A");
+        assert!((nearest.distance_m - 22.2).abs() < 1.0);
+        assert!(file
+            .reverse_geocode_address(64.1500, -21.9377, 50.0)
+            .unwrap()
+            .is_none());
+
+        let options = ImportOptions {
+            include_tags: Some(vec!["building".into()]),
+            exclude_tags: vec![],
+            ..ImportOptions::default()
+        };
+        let file = TOSMFile::from_json_str_with(SOURCE, &options).unwrap();
+        assert!(file.geocode_address("Fjólugata 5").is_empty());
+    }
+}
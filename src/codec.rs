@@ -0,0 +1,207 @@
+//! Payload encodings and compression codecs for `.tosm` files.
+//!
+//! The ids of both are stored in the container header, so readers detect them automatically;
+//! the level settings only affect writing.
+
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::TosmError;
+
+/// Compression applied to the encoded payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    /// `quality` ranges from 0 to 11, `window` is the log2 of the window size (10 to 24).
+    Brotli {
+        quality: u32,
+        window: u32,
+    },
+    /// `level` ranges from 0 to 9.
+    Gzip {
+        level: u32,
+    },
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Brotli {
+            quality: 4,
+            window: 21,
+        }
+    }
+}
+
+impl Codec {
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Brotli { .. } => 1,
+            Codec::Gzip { .. } => 2,
+        }
+    }
+
+    /// Checks the settings against the ranges documented on each codec.
+    fn check(&self) -> Result<(), TosmError> {
+        let out_of_range = |setting: &str, value: u32, range: std::ops::RangeInclusive<u32>| {
+            if range.contains(&value) {
+                Ok(())
+            } else {
+                Err(TosmError::InvalidCodecSetting(format!(
+                    "{} {} is outside {}..={}",
+                    setting,
+                    value,
+                    range.start(),
+                    range.end()
+                )))
+            }
+        };
+        match *self {
+            Codec::None => Ok(()),
+            Codec::Brotli { quality, window } => {
+                out_of_range("brotli quality", quality, 0..=11)?;
+                out_of_range("brotli window", window, 10..=24)
+            }
+            Codec::Gzip { level } => out_of_range("gzip level", level, 0..=9),
+        }
+    }
+
+    pub(crate) fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, TosmError> {
+        self.check()?;
+        match *self {
+            Codec::None => Ok(data),
+            Codec::Brotli { quality, window } => {
+                let mut compressor =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, quality, window);
+                compressor.write_all(&data)?;
+                Ok(compressor.into_inner())
+            }
+            Codec::Gzip { level } => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(&data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+}
+
+/// Decompresses a payload written with the codec identified by `id`, which must come to
+/// exactly `len` bytes. Inflation stops just past `len`, so a forged payload can't exhaust
+/// memory.
+pub(crate) fn decompress(id: u8, payload: &[u8], len: u64) -> Result<Vec<u8>, TosmError> {
+    let mut out = vec![];
+    let limit = len.saturating_add(1);
+    match id {
+        0 => out.extend_from_slice(payload),
+        1 => {
+            brotli::Decompressor::new(payload, 4096)
+                .take(limit)
+                .read_to_end(&mut out)?;
+        }
+        2 => {
+            GzDecoder::new(payload).take(limit).read_to_end(&mut out)?;
+        }
+        _ => return Err(TosmError::UnsupportedCodec(id)),
+    };
+    if out.len() as u64 != len {
+        return Err(TosmError::DecodedLengthMismatch { expected: len });
+    }
+    Ok(out)
+}
+
+/// Serialization format of the payload before compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Bincode,
+    Cbor,
+}
+
+impl Encoding {
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            Encoding::Bincode => 1,
+            Encoding::Cbor => 2,
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, TosmError> {
+        match self {
+            Encoding::Bincode => bincode::serialize(value).map_err(TosmError::Encode),
+            Encoding::Cbor => serde_cbor::to_vec(value).map_err(TosmError::Cbor),
+        }
+    }
+}
+
+/// Decodes a payload written with the encoding identified by `id`.
+pub(crate) fn decode<T: DeserializeOwned>(id: u8, data: &[u8]) -> Result<T, TosmError> {
+    match id {
+        1 => bincode::deserialize(data).map_err(TosmError::Decode),
+        2 => serde_cbor::from_slice(data).map_err(TosmError::Cbor),
+        _ => Err(TosmError::UnsupportedEncoding(id)),
+    }
+}
+
+/// How a `.tosm` file is written. The default is bincode compressed with brotli at quality 4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WriteOptions {
+    pub encoding: Encoding,
+    pub codec: Codec,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, Codec};
+    use crate::TosmError;
+
+    #[test]
+    fn round_trips_and_limits_payloads() {
+        let data: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        for codec in [
+            Codec::None,
+            Codec::default(),
+            Codec::Brotli {
+                quality: 11,
+                window: 24,
+            },
+            Codec::Gzip { level: 0 },
+            Codec::Gzip { level: 9 },
+        ] {
+            let payload = codec.compress(data.clone()).unwrap();
+            let len = data.len() as u64;
+            assert_eq!(decompress(codec.id(), &payload, len).unwrap(), data);
+            // A header understating the length stops inflation instead of trusting the data.
+            assert!(matches!(
+                decompress(codec.id(), &payload, len / 2),
+                Err(TosmError::DecodedLengthMismatch { .. })
+            ));
+        }
+
+        assert!(matches!(
+            decompress(3, &data, data.len() as u64),
+            Err(TosmError::UnsupportedCodec(3))
+        ));
+        for codec in [
+            Codec::Brotli {
+                quality: 12,
+                window: 21,
+            },
+            Codec::Brotli {
+                quality: 4,
+                window: 9,
+            },
+            Codec::Gzip { level: 10 },
+        ] {
+            assert!(matches!(
+                codec.compress(data.clone()),
+                Err(TosmError::InvalidCodecSetting(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! The `.tosm` container: a fixed-size header followed by the compressed payload.
+//!
+//! All header fields are little-endian:
+//!
+//! | offset | size | field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | magic `TOSM`                               |
+//! | 4      | 2    | format version                             |
+//! | 6      | 1    | compression codec id                       |
+//! | 7      | 1    | payload encoding id                        |
+//! | 8      | 4    | flags, bit 0: payload has contraction hierarchies |
+//! | 12     | 32   | bounding box: min lat, min lon, max lat, max lon (`f64`, NaN when empty) |
+//! | 44     | 8    | node count                                 |
+//! | 52     | 8    | way count                                  |
+//! | 60     | 8    | source timestamp, unix seconds (`i64::MIN` when unknown) |
+//! | 68     | 8    | payload length in bytes                    |
+//! | 76     | 4    | CRC-32 of the payload                      |
+//! | 80     | 8    | payload length after decompression         |
+//!
+//! Encoding id 3 marks the uncompressed flat layout described in the `flat` module.
+
+use std::io::{Read, Write};
+
+use crate::codec::{self, WriteOptions};
+use crate::{TOSMFile, TosmError};
+
+const MAGIC: &[u8; 4] = b"TOSM";
+/// Version of the header and payload layout. It changes once per release that changes the
+/// layout, not with every change in between; readers accept only their own version.
+pub const FORMAT_VERSION: u16 = 1;
+
+pub(crate) const HEADER_LEN: usize = 88;
+const NO_TIMESTAMP: i64 = i64::MIN;
+const FLAG_CONTRACTION_HIERARCHY: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_lon: f64,
+    pub max_lat: f64,
+    pub max_lon: f64,
+}
+
+/// Metadata stored in front of every `.tosm` payload, readable without decoding the payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub codec: u8,
+    pub encoding: u8,
+    pub contraction_hierarchy: bool,
+    pub bbox: Option<BoundingBox>,
+    pub node_count: u64,
+    pub way_count: u64,
+    pub source_timestamp: Option<i64>,
+    pub payload_len: u64,
+    pub checksum: u32,
+    /// Length of the payload once decompressed, which bounds what readers inflate.
+    pub decoded_len: u64,
+}
+
+impl Header {
+    pub(crate) fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let bbox = self.bbox.unwrap_or(BoundingBox {
+            min_lat: f64::NAN,
+            min_lon: f64::NAN,
+            max_lat: f64::NAN,
+            max_lon: f64::NAN,
+        });
+
+        let mut out = [0u8; HEADER_LEN];
+        out[0..4].copy_from_slice(MAGIC);
+        out[4..6].copy_from_slice(&self.version.to_le_bytes());
+        out[6] = self.codec;
+        out[7] = self.encoding;
+        if self.contraction_hierarchy {
+            out[8..12].copy_from_slice(&FLAG_CONTRACTION_HIERARCHY.to_le_bytes());
+        }
+        out[12..20].copy_from_slice(&bbox.min_lat.to_le_bytes());
+        out[20..28].copy_from_slice(&bbox.min_lon.to_le_bytes());
+        out[28..36].copy_from_slice(&bbox.max_lat.to_le_bytes());
+        out[36..44].copy_from_slice(&bbox.max_lon.to_le_bytes());
+        out[44..52].copy_from_slice(&self.node_count.to_le_bytes());
+        out[52..60].copy_from_slice(&self.way_count.to_le_bytes());
+        let timestamp = self.source_timestamp.unwrap_or(NO_TIMESTAMP);
+        out[60..68].copy_from_slice(&timestamp.to_le_bytes());
+        out[68..76].copy_from_slice(&self.payload_len.to_le_bytes());
+        out[76..80].copy_from_slice(&self.checksum.to_le_bytes());
+        out[80..88].copy_from_slice(&self.decoded_len.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Result<Self, TosmError> {
+        if &bytes[0..4] != MAGIC {
+            return Err(TosmError::NotATosmFile);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(TosmError::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        let f64_at = |i: usize| f64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        let bbox = BoundingBox {
+            min_lat: f64_at(12),
+            min_lon: f64_at(20),
+            max_lat: f64_at(28),
+            max_lon: f64_at(36),
+        };
+        let timestamp = u64_at(60) as i64;
+        let flags = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+
+        Ok(Header {
+            version,
+            codec: bytes[6],
+            encoding: bytes[7],
+            contraction_hierarchy: flags & FLAG_CONTRACTION_HIERARCHY != 0,
+            bbox: if bbox.min_lat.is_nan() {
+                None
+            } else {
+                Some(bbox)
+            },
+            node_count: u64_at(44),
+            way_count: u64_at(52),
+            source_timestamp: if timestamp == NO_TIMESTAMP {
+                None
+            } else {
+                Some(timestamp)
+            },
+            payload_len: u64_at(68),
+            checksum: u32::from_le_bytes(bytes[76..80].try_into().unwrap()),
+            decoded_len: u64_at(80),
+        })
+    }
+}
+
+pub(crate) fn checksum(payload: &[u8]) -> u32 {
+    let mut crc = flate2::Crc::new();
+    crc.update(payload);
+    crc.sum()
+}
+
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<Header, TosmError> {
+    let mut bytes = [0u8; HEADER_LEN];
+    reader.read_exact(&mut bytes).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            TosmError::NotATosmFile
+        } else {
+            e.into()
+        }
+    })?;
+
+    Header::from_bytes(&bytes)
+}
+
+pub(crate) fn write<W: Write>(
+    file: &TOSMFile,
+    mut writer: W,
+    options: &WriteOptions,
+) -> Result<(), TosmError> {
+    let data = options.encoding.encode(file)?;
+    let decoded_len = data.len() as u64;
+    let payload = options.codec.compress(data)?;
+
+    let header = Header {
+        version: FORMAT_VERSION,
+        codec: options.codec.id(),
+        encoding: options.encoding.id(),
+        contraction_hierarchy: !file.contraction_hierarchies.is_empty(),
+        bbox: file.bounding_box(),
+        node_count: file.nodes().len() as u64,
+        way_count: file.ways().len() as u64,
+        source_timestamp: file.source_timestamp(),
+        payload_len: payload.len() as u64,
+        checksum: checksum(&payload),
+        decoded_len,
+    };
+
+    writer.write_all(&header.to_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+pub(crate) fn read<R: Read>(mut reader: R) -> Result<TOSMFile, TosmError> {
+    let header = read_header(&mut reader)?;
+
+    let mut payload = vec![];
+    reader.take(header.payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != header.payload_len {
+        return Err(TosmError::TruncatedPayload {
+            expected: header.payload_len,
+            found: payload.len() as u64,
+        });
+    }
+
+    let found = checksum(&payload);
+    if found != header.checksum {
+        return Err(TosmError::ChecksumMismatch {
+            expected: header.checksum,
+            found,
+        });
+    }
+
+    let data = codec::decompress(header.codec, &payload, header.decoded_len)?;
+    let mut file: TOSMFile = codec::decode(header.encoding, &data)?;
+    file.build_indexes();
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, read_header, write, FORMAT_VERSION};
+    use crate::{Codec, Encoding, TOSMFile, TosmError, WriteOptions};
+
+    fn sample() -> TOSMFile {
+        TOSMFile::from_json_str(
+            r#"{
+                "timestamp": 1650000000,
+                "nodes": [
+                    {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                    {"id": 2, "lat": 64.1430, "lon": -21.9390}
+                ],
+                "ways": [{"id": 10, "node_ids": [1, 2], "one_way": false, "name": null}]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn writes_header() {
+        let mut blob = vec![];
+        write(&sample(), &mut blob, &WriteOptions::default()).unwrap();
+
+        let header = read_header(&mut &blob[..]).unwrap();
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.node_count, 2);
+        assert_eq!(header.way_count, 1);
+        assert_eq!(header.source_timestamp, Some(1650000000));
+        assert!(!header.contraction_hierarchy);
+        assert_eq!(header.bbox.unwrap().max_lat, 64.1430);
+        assert_eq!(header.bbox.unwrap().min_lon, -21.9390);
+
+        assert_eq!(read(&blob[..]).unwrap().nodes().len(), 2);
+    }
+
+    #[test]
+    fn rejects_incompatible_files() {
+        let mut blob = vec![];
+        write(&sample(), &mut blob, &WriteOptions::default()).unwrap();
+
+        let mut future = blob.clone();
+        future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            read(&future[..]),
+            Err(TosmError::UnsupportedVersion { .. })
+        ));
+
+        let mut corrupt = blob.clone();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(
+            read(&corrupt[..]),
+            Err(TosmError::ChecksumMismatch { .. })
+        ));
+
+        assert!(matches!(
+            read(&blob[..blob.len() - 1]),
+            Err(TosmError::TruncatedPayload { .. })
+        ));
+        assert!(matches!(
+            read(&b"\x1b\x00garbage"[..]),
+            Err(TosmError::NotATosmFile)
+        ));
+
+        let mut unknown_codec = blob;
+        unknown_codec[6] = 0xff;
+        assert!(matches!(
+            read(&unknown_codec[..]),
+            Err(TosmError::UnsupportedCodec(0xff))
+        ));
+    }
+
+    #[test]
+    fn detects_codec_and_encoding() {
+        let codecs = [
+            Codec::None,
+            Codec::default(),
+            Codec::Brotli {
+                quality: 11,
+                window: 22,
+            },
+            Codec::Gzip { level: 6 },
+        ];
+
+        for encoding in [Encoding::Bincode, Encoding::Cbor] {
+            for codec in codecs {
+                let mut blob = vec![];
+                write(&sample(), &mut blob, &WriteOptions { encoding, codec }).unwrap();
+
+                let file = read(&blob[..]).unwrap();
+                assert_eq!(file.nodes().len(), 2);
+                assert_eq!(file.nearest_node(64.1431, -21.9391).unwrap(), Some(2));
+            }
+        }
+    }
+}
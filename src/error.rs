@@ -0,0 +1,129 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TosmError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// A coordinate that is not finite or lies outside the valid lat/lon range. `node_id` is
+    /// `None` when the coordinate came from a query rather than from the data.
+    InvalidCoordinate {
+        node_id: Option<u64>,
+        lat: f64,
+        lon: f64,
+    },
+    /// A way references a node id that is not part of the file.
+    DanglingNodeRef {
+        way_id: u64,
+        node_id: u64,
+    },
+    /// The node id is not part of the routing graph.
+    UnknownNode(u64),
+    InvalidPbf(String),
+    InvalidXml(String),
+    /// The data does not start with the `.tosm` magic bytes. Files written before the container
+    /// format existed (raw brotli-compressed bincode) also end up here and must be regenerated.
+    NotATosmFile,
+    UnsupportedVersion {
+        found: u16,
+        supported: u16,
+    },
+    UnsupportedCodec(u8),
+    UnsupportedEncoding(u8),
+    TruncatedPayload {
+        expected: u64,
+        found: u64,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// The payload does not decompress to the length given in the header.
+    DecodedLengthMismatch {
+        expected: u64,
+    },
+    /// A compression setting outside the range the codec supports.
+    InvalidCodecSetting(String),
+    /// A flat `.tosm` file whose sections do not fit its payload, or a file too large for the
+    /// flat layout.
+    InvalidFlatLayout(String),
+    Encode(bincode::Error),
+    Decode(bincode::Error),
+    Cbor(serde_cbor::Error),
+}
+
+impl fmt::Display for TosmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TosmError::Io(e) => write!(f, "i/o error: {}", e),
+            TosmError::Json(e) => write!(f, "invalid source json: {}", e),
+            TosmError::InvalidCoordinate {
+                node_id: Some(id),
+                lat,
+                lon,
+            } => write!(f, "node {} has invalid coordinate ({}, {})", id, lat, lon),
+            TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            } => write!(f, "invalid coordinate ({}, {})", lat, lon),
+            TosmError::DanglingNodeRef { way_id, node_id } => {
+                write!(f, "way {} references missing node {}", way_id, node_id)
+            }
+            TosmError::UnknownNode(id) => write!(f, "node {} is not on any routable way", id),
+            TosmError::InvalidPbf(e) => write!(f, "invalid osm pbf: {}", e),
+            TosmError::InvalidXml(e) => write!(f, "invalid osm xml: {}", e),
+            TosmError::NotATosmFile => write!(f, "not a tosm file (missing header)"),
+            TosmError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "unsupported tosm format version {} (this build reads version {})",
+                found, supported
+            ),
+            TosmError::UnsupportedCodec(id) => write!(f, "unsupported compression codec {}", id),
+            TosmError::UnsupportedEncoding(id) => write!(f, "unsupported payload encoding {}", id),
+            TosmError::TruncatedPayload { expected, found } => write!(
+                f,
+                "truncated payload: expected {} bytes, found {}",
+                expected, found
+            ),
+            TosmError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "payload checksum mismatch: expected {:08x}, found {:08x}",
+                expected, found
+            ),
+            TosmError::DecodedLengthMismatch { expected } => write!(
+                f,
+                "payload does not decompress to the {} bytes given in the header",
+                expected
+            ),
+            TosmError::InvalidCodecSetting(e) => write!(f, "invalid compression setting: {}", e),
+            TosmError::InvalidFlatLayout(e) => write!(f, "invalid flat tosm layout: {}", e),
+            TosmError::Encode(e) => write!(f, "failed to encode tosm data: {}", e),
+            TosmError::Decode(e) => write!(f, "failed to decode tosm data: {}", e),
+            TosmError::Cbor(e) => write!(f, "failed to process cbor tosm data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TosmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TosmError::Io(e) => Some(e),
+            TosmError::Json(e) => Some(e),
+            TosmError::Encode(e) | TosmError::Decode(e) => Some(e),
+            TosmError::Cbor(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TosmError {
+    fn from(e: std::io::Error) -> Self {
+        TosmError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for TosmError {
+    fn from(e: serde_json::Error) -> Self {
+        TosmError::Json(e)
+    }
+}
@@ -0,0 +1,715 @@
+//! The flat `.tosm` layout: an uncompressed payload of fixed-size records that is queried in
+//! place, e.g. from a memory-mapped file, instead of being decoded into a [`TOSMFile`].
+//!
+//! The payload follows the usual container header, with codec 0 and encoding id 3. It holds
+//! the nodes and ways with their names, not tags, relations or contraction hierarchies. All
+//! integers and floats are little-endian; sections start at multiples of 8 bytes.
+//!
+//! | section | size      | contents                                                        |
+//! |---------|-----------|-----------------------------------------------------------------|
+//! | counts  | 32        | node count `N`, way count `W`, node ref count `R`, name bytes `S` (`u64`) |
+//! | nodes   | `N` × 24  | id (`u64`), lat, lon (`f64`), sorted by id                      |
+//! | ways    | `W` × 32  | id (`u64`), first ref (`u64`), name offset, name length, flags (`u32`), padding; sorted by id |
+//! | refs    | `R` × 4   | node record index of each way node (`u32`), way after way       |
+//! | tree    | `N` × 4   | node record indexes forming a balanced k-d tree                 |
+//! | names   | `S`       | UTF-8 way names                                                 |
+//!
+//! Way flags: bit 0 `one_way`, bit 1 has a name, bit 2 `roundabout`. The k-d tree is stored
+//! implicitly: the root of a range of the array is its middle entry, with the left subtree
+//! before it and the right subtree after it. It splits on the x, y and z coordinates of the
+//! nodes on the unit sphere in turn, so it needs no special cases at the poles or the
+//! antimeridian.
+//!
+//! Only nodes are indexed by location, so [`FlatTosm`] answers node lookups and nearest and
+//! radius node queries. Way queries such as [`TOSMFile::nearest_way`],
+//! [`TOSMFile::ways_in_bbox`] and [`TOSMFile::nodes_in_bbox`], as well as routing and
+//! geocoding, need the file decoded into a [`TOSMFile`].
+
+use std::io::Write;
+use std::ops::Range;
+
+use crate::container::{self, Header, FORMAT_VERSION, HEADER_LEN};
+use crate::{check_coordinate, dist_haversine, Node, NodeDistance, TOSMFile, TosmError};
+
+pub(crate) const FLAT_ENCODING: u8 = 3;
+
+const COUNTS_LEN: usize = 32;
+const NODE_LEN: usize = 24;
+const WAY_LEN: usize = 32;
+
+const ONE_WAY: u32 = 1;
+const NAMED: u32 = 2;
+const ROUNDABOUT: u32 = 4;
+
+/// Earth radius used by [`dist_haversine`], in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// A `.tosm` file in the flat layout, read directly from its bytes.
+///
+/// `B` is any byte storage, such as a `Vec<u8>` or, with the `mmap` feature, a memory map
+/// opened with `FlatTosm::open`. [`FlatTosm::new`] checks every record once, after which
+/// lookups read only the records they need and never panic.
+#[derive(Debug)]
+pub struct FlatTosm<B> {
+    data: B,
+    header: Header,
+    node_count: usize,
+    way_count: usize,
+    ref_count: usize,
+    nodes: usize,
+    ways: usize,
+    refs: usize,
+    tree: usize,
+    names: Range<usize>,
+}
+
+/// A way of a [`FlatTosm`], borrowing from its bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct FlatWay<'a> {
+    id: u64,
+    flags: u32,
+    name: &'a str,
+    refs: &'a [u8],
+    nodes: &'a [u8],
+}
+
+impl<B: AsRef<[u8]>> FlatTosm<B> {
+    /// Reads a flat file from `data`, verifying its header, layout and checksum, and that the
+    /// way and tree records only point at nodes, refs and names inside the file.
+    pub fn new(data: B) -> Result<Self, TosmError> {
+        let file = Self::new_unverified(data)?;
+        file.verify_checksum()?;
+        file.verify_records()?;
+        Ok(file)
+    }
+
+    /// Like [`FlatTosm::new`] but only checking the header and the section sizes, without
+    /// reading the payload. Use it for trusted files where startup time matters. Lookups in
+    /// corrupt data then give wrong results or panic, but never cause undefined behaviour.
+    pub fn new_unverified(data: B) -> Result<Self, TosmError> {
+        let bytes = data.as_ref();
+        let header_bytes = bytes.get(..HEADER_LEN).ok_or(TosmError::NotATosmFile)?;
+        let header = Header::from_bytes(header_bytes.try_into().expect("header length"))?;
+        if header.encoding != FLAT_ENCODING {
+            return Err(TosmError::UnsupportedEncoding(header.encoding));
+        }
+        if header.codec != 0 {
+            return Err(TosmError::UnsupportedCodec(header.codec));
+        }
+
+        let payload = &bytes[HEADER_LEN..];
+        if (payload.len() as u64) < header.payload_len {
+            return Err(TosmError::TruncatedPayload {
+                expected: header.payload_len,
+                found: payload.len() as u64,
+            });
+        }
+        let invalid = |reason: &str| TosmError::InvalidFlatLayout(reason.to_string());
+        if payload.len() < COUNTS_LEN {
+            return Err(invalid("missing section counts"));
+        }
+
+        let count = |i: usize| usize::try_from(read_u64(payload, i * 8)).ok();
+        let (Some(node_count), Some(way_count), Some(ref_count), Some(name_len)) =
+            (count(0), count(1), count(2), count(3))
+        else {
+            return Err(invalid("section count out of range"));
+        };
+        let sections = Sections::new(node_count, way_count, ref_count, name_len)
+            .filter(|s| s.end as u64 == header.payload_len)
+            .ok_or_else(|| invalid("section sizes do not match the payload length"))?;
+
+        Ok(FlatTosm {
+            header,
+            node_count,
+            way_count,
+            ref_count,
+            nodes: HEADER_LEN + sections.nodes,
+            ways: HEADER_LEN + sections.ways,
+            refs: HEADER_LEN + sections.refs,
+            tree: HEADER_LEN + sections.tree,
+            names: HEADER_LEN + sections.names..HEADER_LEN + sections.end,
+            data,
+        })
+    }
+
+    /// Recomputes the payload checksum and compares it with the header.
+    pub fn verify_checksum(&self) -> Result<(), TosmError> {
+        let payload = &self.data.as_ref()[HEADER_LEN..self.names.end];
+        let found = container::checksum(payload);
+        if found != self.header.checksum {
+            return Err(TosmError::ChecksumMismatch {
+                expected: self.header.checksum,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the refs of each way follow those of the previous one, and that refs,
+    /// tree entries and names lie within their sections.
+    fn verify_records(&self) -> Result<(), TosmError> {
+        let data = self.data.as_ref();
+        let invalid = |reason: &str| Err(TosmError::InvalidFlatLayout(reason.to_string()));
+        let node_ref = |at: usize| (read_u32(data, at) as usize) < self.node_count;
+
+        let mut previous = 0;
+        for i in 0..self.way_count {
+            let at = self.ways + i * WAY_LEN;
+            let first = read_u64(data, at + 8);
+            if first < previous || first > self.ref_count as u64 {
+                return invalid("way refs out of order");
+            }
+            previous = first;
+            let name_end = read_u32(data, at + 16) as usize + read_u32(data, at + 20) as usize;
+            if name_end > self.names.len() {
+                return invalid("way name out of range");
+            }
+        }
+        if !(0..self.ref_count).all(|i| node_ref(self.refs + i * 4)) {
+            return invalid("way node ref out of range");
+        }
+        if !(0..self.node_count).all(|i| node_ref(self.tree + i * 4)) {
+            return invalid("tree entry out of range");
+        }
+        Ok(())
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn way_count(&self) -> usize {
+        self.way_count
+    }
+
+    /// Looks up a node by id with a binary search over the node records.
+    pub fn node(&self, id: u64) -> Option<Node> {
+        let index = binary_search(self.node_count, id, |i| self.node_id(i))?;
+        Some(self.node_at(index))
+    }
+
+    /// The nodes, sorted by id.
+    pub fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        (0..self.node_count).map(|i| self.node_at(i))
+    }
+
+    /// Looks up a way by id with a binary search over the way records.
+    pub fn way(&self, id: u64) -> Option<FlatWay<'_>> {
+        let index = binary_search(self.way_count, id, |i| {
+            read_u64(self.data.as_ref(), self.ways + i * WAY_LEN)
+        })?;
+        Some(self.way_at(index))
+    }
+
+    /// The ways, sorted by id.
+    pub fn ways(&self) -> impl Iterator<Item = FlatWay<'_>> + '_ {
+        (0..self.way_count).map(|i| self.way_at(i))
+    }
+
+    /// Returns the id of the node closest to the given coordinate, or `None` for an empty file.
+    pub fn nearest_node(&self, lat: f64, lon: f64) -> Result<Option<u64>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let query = unit_vector(lat, lon);
+        let (mut bound, mut best) = (f64::INFINITY, None);
+        self.visit(
+            0..self.node_count,
+            0,
+            query,
+            &mut bound,
+            &mut |me, index, chord2, bound| {
+                if chord2 < *bound {
+                    *bound = chord2;
+                    best = Some(me.node_id(index));
+                }
+            },
+        );
+        Ok(best)
+    }
+
+    /// Returns every node within `radius_m` metres, closest first.
+    pub fn nodes_within(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+    ) -> Result<Vec<NodeDistance>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let angle = (radius_m / 1000.0 / EARTH_RADIUS_KM).min(std::f64::consts::PI);
+        let chord = 2.0 * (angle / 2.0).sin();
+        let mut bound = chord * chord;
+
+        let mut nodes = vec![];
+        let query = unit_vector(lat, lon);
+        self.visit(
+            0..self.node_count,
+            0,
+            query,
+            &mut bound,
+            &mut |me, index, chord2, bound| {
+                if chord2 <= *bound {
+                    let node = me.node_at(index);
+                    nodes.push(NodeDistance {
+                        node_id: node.id(),
+                        distance_m: dist_haversine(&[lat, lon], &[node.lat(), node.lon()]) * 1000.0,
+                    });
+                }
+            },
+        );
+        nodes.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+        Ok(nodes)
+    }
+
+    /// Walks the k-d tree over `range` of the tree array, calling `found` with each node
+    /// record index that may lie within `bound`, its squared chord distance from `query` and
+    /// the bound, which `found` may tighten. Subtrees further away than the bound are skipped.
+    fn visit<F>(
+        &self,
+        range: Range<usize>,
+        depth: usize,
+        query: [f64; 3],
+        bound: &mut f64,
+        found: &mut F,
+    ) where
+        F: FnMut(&Self, usize, f64, &mut f64),
+    {
+        if range.is_empty() {
+            return;
+        }
+        let mid = range.start + range.len() / 2;
+        let index = read_u32(self.data.as_ref(), self.tree + mid * 4) as usize;
+        let node = self.node_at(index);
+        let p = unit_vector(node.lat(), node.lon());
+        let chord2 = (0..3).map(|d| (p[d] - query[d]).powi(2)).sum();
+        found(self, index, chord2, bound);
+
+        let axis = depth % 3;
+        let diff = query[axis] - p[axis];
+        let (near, far) = if diff < 0.0 {
+            (range.start..mid, mid + 1..range.end)
+        } else {
+            (mid + 1..range.end, range.start..mid)
+        };
+        self.visit(near, depth + 1, query, bound, found);
+        if diff * diff <= *bound {
+            self.visit(far, depth + 1, query, bound, found);
+        }
+    }
+
+    fn node_id(&self, index: usize) -> u64 {
+        read_u64(self.data.as_ref(), self.nodes + index * NODE_LEN)
+    }
+
+    fn node_at(&self, index: usize) -> Node {
+        let data = self.data.as_ref();
+        let at = self.nodes + index * NODE_LEN;
+        Node::new(
+            read_u64(data, at),
+            read_f64(data, at + 8),
+            read_f64(data, at + 16),
+        )
+    }
+
+    fn way_at(&self, index: usize) -> FlatWay<'_> {
+        let data = self.data.as_ref();
+        let at = self.ways + index * WAY_LEN;
+        let first = read_u64(data, at + 8) as usize;
+        let end = if index + 1 < self.way_count {
+            read_u64(data, at + WAY_LEN + 8) as usize
+        } else {
+            self.ref_count
+        };
+        let name_start = self.names.start + read_u32(data, at + 16) as usize;
+        let name_len = read_u32(data, at + 20) as usize;
+
+        FlatWay {
+            id: read_u64(data, at),
+            flags: read_u32(data, at + 24),
+            name: std::str::from_utf8(&data[name_start..name_start + name_len]).unwrap_or(""),
+            refs: &data[self.refs + first * 4..self.refs + end * 4],
+            nodes: &data[self.nodes..self.nodes + self.node_count * NODE_LEN],
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl FlatTosm<memmap2::Mmap> {
+    /// Memory-maps a flat `.tosm` file and reads it without verifying it (see
+    /// [`FlatTosm::new_unverified`]), so opening does not touch the payload. Processes that
+    /// map the same file share its pages.
+    ///
+    /// Lookups in a corrupt file may panic; use [`FlatTosm::open_verified`] for files that
+    /// aren't trusted.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, TosmError> {
+        Self::new_unverified(map(path.as_ref())?)
+    }
+
+    /// Like [`FlatTosm::open`] but verifying the file as [`FlatTosm::new`] does, which reads
+    /// the whole payload once.
+    pub fn open_verified<P: AsRef<std::path::Path>>(path: P) -> Result<Self, TosmError> {
+        Self::new(map(path.as_ref())?)
+    }
+}
+
+#[cfg(feature = "mmap")]
+fn map(path: &std::path::Path) -> Result<memmap2::Mmap, TosmError> {
+    let file = std::fs::File::open(path)?;
+    // Safety: the map is only read through `&[u8]`. Modifying the file while it is mapped
+    // can change what queries return, which is why the file should not be rewritten in
+    // place; write a new file and rename it instead.
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
+
+impl<'a> FlatWay<'a> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn one_way(&self) -> bool {
+        self.flags & ONE_WAY != 0
+    }
+
+    pub fn roundabout(&self) -> bool {
+        self.flags & ROUNDABOUT != 0
+    }
+
+    pub fn name(&self) -> Option<&'a str> {
+        (self.flags & NAMED != 0).then_some(self.name)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.refs.len() / 4
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = u64> + 'a {
+        let nodes = self.nodes;
+        self.refs.chunks_exact(4).map(move |r| {
+            read_u64(
+                nodes,
+                u32::from_le_bytes(r.try_into().unwrap()) as usize * NODE_LEN,
+            )
+        })
+    }
+
+    /// `[lat, lon]` of the way's nodes.
+    pub fn coords(&self) -> impl Iterator<Item = [f64; 2]> + 'a {
+        let nodes = self.nodes;
+        self.refs.chunks_exact(4).map(move |r| {
+            let at = u32::from_le_bytes(r.try_into().unwrap()) as usize * NODE_LEN;
+            [read_f64(nodes, at + 8), read_f64(nodes, at + 16)]
+        })
+    }
+}
+
+/// Byte offsets of the sections within the payload.
+struct Sections {
+    nodes: usize,
+    ways: usize,
+    refs: usize,
+    tree: usize,
+    names: usize,
+    end: usize,
+}
+
+impl Sections {
+    /// The layout for the given counts, or `None` if it does not fit in memory.
+    fn new(node_count: usize, way_count: usize, ref_count: usize, name_len: usize) -> Option<Self> {
+        let nodes = COUNTS_LEN;
+        let ways = nodes.checked_add(node_count.checked_mul(NODE_LEN)?)?;
+        let refs = ways.checked_add(way_count.checked_mul(WAY_LEN)?)?;
+        let tree = align8(refs.checked_add(ref_count.checked_mul(4)?)?)?;
+        let names = align8(tree.checked_add(node_count.checked_mul(4)?)?)?;
+        let end = names.checked_add(name_len)?;
+        Some(Sections {
+            nodes,
+            ways,
+            refs,
+            tree,
+            names,
+            end,
+        })
+    }
+}
+
+/// Writes `file` in the flat layout, header included.
+pub(crate) fn write<W: Write>(file: &TOSMFile, mut writer: W) -> Result<(), TosmError> {
+    let too_large = || TosmError::InvalidFlatLayout("more than 2^32 nodes".to_string());
+    if u32::try_from(file.nodes.len()).is_err() {
+        return Err(too_large());
+    }
+
+    let mut nodes: Vec<&Node> = file.nodes.iter().collect();
+    nodes.sort_by_key(|n| n.id);
+    let node_index: std::collections::HashMap<u64, u32> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.id, i as u32))
+        .collect();
+    let mut ways: Vec<_> = file.ways.iter().collect();
+    ways.sort_by_key(|w| w.id);
+
+    let ref_count: usize = ways.iter().map(|w| w.node_ids.len()).sum();
+    let name_len: usize = ways.iter().filter_map(|w| w.name()).map(str::len).sum();
+    let sections = Sections::new(nodes.len(), ways.len(), ref_count, name_len)
+        .expect("layout of an in-memory file fits");
+
+    let mut payload = Vec::with_capacity(sections.end);
+    for count in [nodes.len(), ways.len(), ref_count, name_len] {
+        payload.extend_from_slice(&(count as u64).to_le_bytes());
+    }
+    for node in &nodes {
+        payload.extend_from_slice(&node.id.to_le_bytes());
+        payload.extend_from_slice(&node.lat.to_le_bytes());
+        payload.extend_from_slice(&node.lon.to_le_bytes());
+    }
+
+    let (mut first_ref, mut name_offset) = (0u64, 0u32);
+    for way in &ways {
+        let name = way.name().unwrap_or("");
+        let flags = if way.one_way { ONE_WAY } else { 0 }
+            | if way.name.is_some() { NAMED } else { 0 }
+            | if way.roundabout { ROUNDABOUT } else { 0 };
+        payload.extend_from_slice(&way.id.to_le_bytes());
+        payload.extend_from_slice(&first_ref.to_le_bytes());
+        payload.extend_from_slice(&name_offset.to_le_bytes());
+        payload.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&flags.to_le_bytes());
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        first_ref += way.node_ids.len() as u64;
+        name_offset = u32::try_from(name_offset as usize + name.len()).map_err(|_| {
+            TosmError::InvalidFlatLayout("more than 4 GiB of way names".to_string())
+        })?;
+    }
+
+    for way in &ways {
+        for id in &way.node_ids {
+            let index = node_index.get(id).ok_or_else(|| {
+                TosmError::InvalidFlatLayout(format!(
+                    "way {} references missing node {}",
+                    way.id, id
+                ))
+            })?;
+            payload.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+    payload.resize(sections.tree, 0);
+
+    let points: Vec<[f64; 3]> = nodes.iter().map(|n| unit_vector(n.lat, n.lon)).collect();
+    let mut tree: Vec<u32> = (0..nodes.len() as u32).collect();
+    build_tree(&mut tree, &points, 0);
+    for index in tree {
+        payload.extend_from_slice(&index.to_le_bytes());
+    }
+    payload.resize(sections.names, 0);
+
+    for way in &ways {
+        payload.extend_from_slice(way.name().unwrap_or("").as_bytes());
+    }
+    debug_assert_eq!(payload.len(), sections.end);
+
+    let header = Header {
+        version: FORMAT_VERSION,
+        codec: 0,
+        encoding: FLAT_ENCODING,
+        contraction_hierarchy: false,
+        bbox: file.bounding_box(),
+        node_count: nodes.len() as u64,
+        way_count: ways.len() as u64,
+        source_timestamp: file.source_timestamp(),
+        payload_len: payload.len() as u64,
+        decoded_len: payload.len() as u64,
+        checksum: container::checksum(&payload),
+    };
+    writer.write_all(&header.to_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// Orders `tree` so that the middle entry of every range splits the rest on the axis of
+/// its depth, as [`FlatTosm::visit`] expects.
+fn build_tree(tree: &mut [u32], points: &[[f64; 3]], depth: usize) {
+    if tree.len() <= 1 {
+        return;
+    }
+    let mid = tree.len() / 2;
+    let axis = depth % 3;
+    tree.select_nth_unstable_by(mid, |&a, &b| {
+        points[a as usize][axis].total_cmp(&points[b as usize][axis])
+    });
+    let (left, right) = tree.split_at_mut(mid);
+    build_tree(left, points, depth + 1);
+    build_tree(&mut right[1..], points, depth + 1);
+}
+
+fn unit_vector(lat: f64, lon: f64) -> [f64; 3] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+fn align8(n: usize) -> Option<usize> {
+    Some(n.checked_add(7)? & !7)
+}
+
+/// Index of the record with `id` among `count` records sorted by id.
+fn binary_search(count: usize, id: u64, id_at: impl Fn(usize) -> u64) -> Option<usize> {
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match id_at(mid).cmp(&id) {
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => return Some(mid),
+        }
+    }
+    None
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn read_f64(data: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlatTosm;
+    use crate::container::{self, Header, HEADER_LEN};
+    use crate::{TOSMFile, TosmError};
+
+    /// Recomputes the checksum in the header of a flat file after it was edited.
+    fn reseal(blob: &mut [u8]) {
+        let mut header = Header::from_bytes(blob[..HEADER_LEN].try_into().unwrap()).unwrap();
+        header.checksum = container::checksum(&blob[HEADER_LEN..]);
+        blob[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+    }
+
+    #[test]
+    fn queries_flat_files_in_place() {
+        let source = r#"{
+            "nodes": [
+                {"id": 30, "lat": 64.1420, "lon": -21.9390},
+                {"id": 10, "lat": 64.1420, "lon": -21.9380},
+                {"id": 20, "lat": 64.1430, "lon": -21.9380},
+                {"id": 40, "lat": 65.6800, "lon": -18.1000},
+                {"id": 50, "lat": -16.5000, "lon": 179.9990},
+                {"id": 60, "lat": -16.5000, "lon": -179.9990}
+            ],
+            "ways": [
+                {"id": 2, "node_ids": [30, 10, 20], "one_way": true, "name": "Fjólugata"},
+                {"id": 1, "node_ids": [40, 10], "one_way": false, "name": null}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+        let mut blob = vec![];
+        file.write_flat_tosm(&mut blob).unwrap();
+
+        let flat = FlatTosm::new(&blob[..]).unwrap();
+        assert_eq!(flat.header().node_count, 6);
+        assert_eq!(flat.node_count(), 6);
+        assert_eq!(flat.node(20).unwrap().lat(), 64.1430);
+        assert!(flat.node(25).is_none());
+
+        let way = flat.way(2).unwrap();
+        assert_eq!(way.name(), Some("Fjólugata"));
+        assert!(way.one_way());
+        assert_eq!(way.node_ids().collect::<Vec<_>>(), [30, 10, 20]);
+        assert_eq!(way.coords().nth(1), Some([64.1420, -21.9380]));
+        assert_eq!(flat.way(1).unwrap().name(), None);
+        assert_eq!(flat.ways().map(|w| w.id()).collect::<Vec<_>>(), [1, 2]);
+
+        // Every query agrees with the decoded file, across the antimeridian too.
+        for (lat, lon) in [
+            (64.1421, -21.9389),
+            (65.0, -19.0),
+            (-16.5, -179.9999),
+            (0.0, 0.0),
+        ] {
+            assert_eq!(
+                flat.nearest_node(lat, lon).unwrap(),
+                file.nearest_node(lat, lon).unwrap()
+            );
+            assert_eq!(
+                flat.nodes_within(lat, lon, 200.0).unwrap(),
+                file.nodes_within(lat, lon, 200.0, None).unwrap()
+            );
+        }
+        assert_eq!(flat.nodes_within(-16.5, -179.9999, 200.0).unwrap().len(), 2);
+
+        let mut corrupt = blob.clone();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(
+            FlatTosm::new(&corrupt[..]),
+            Err(TosmError::ChecksumMismatch { .. })
+        ));
+        assert!(FlatTosm::new_unverified(&corrupt[..]).is_ok());
+
+        // Records pointing outside the file are caught even with a matching checksum.
+        let (refs, names) = (flat.refs, flat.names.len());
+        for (at, value) in [(refs + 4, 6u32), (flat.ways + 16, names as u32 + 1)] {
+            let mut corrupt = blob.clone();
+            corrupt[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            reseal(&mut corrupt);
+            assert!(matches!(
+                FlatTosm::new(&corrupt[..]),
+                Err(TosmError::InvalidFlatLayout(_))
+            ));
+        }
+
+        assert!(matches!(
+            FlatTosm::new(&blob[..blob.len() - 1]),
+            Err(TosmError::TruncatedPayload { .. })
+        ));
+        assert!(matches!(
+            TOSMFile::from_tosm_reader(&blob[..]),
+            Err(TosmError::UnsupportedEncoding(3))
+        ));
+
+        let mut dangling = file;
+        dangling.ways[0].node_ids.push(70);
+        assert!(matches!(
+            dangling.write_flat_tosm(&mut vec![]),
+            Err(TosmError::InvalidFlatLayout(_))
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn opens_memory_mapped_files() {
+        let source = r#"{
+            "nodes": [
+                {"id": 10, "lat": 64.1420, "lon": -21.9380},
+                {"id": 20, "lat": 64.1430, "lon": -21.9380}
+            ],
+            "ways": [{"id": 1, "node_ids": [10, 20], "one_way": false, "name": "Fjólugata"}]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+        let mut blob = vec![];
+        file.write_flat_tosm(&mut blob).unwrap();
+        let path = std::env::temp_dir().join(format!("tosm-flat-{}.tosm", std::process::id()));
+        std::fs::write(&path, &blob).unwrap();
+
+        let flat = FlatTosm::open(&path).unwrap();
+        assert_eq!(flat.way(1).unwrap().name(), Some("Fjólugata"));
+        assert_eq!(flat.nearest_node(64.1431, -21.9380).unwrap(), Some(20));
+        assert!(FlatTosm::open_verified(&path).is_ok());
+
+        blob.truncate(blob.len() - 1);
+        std::fs::write(&path, &blob).unwrap();
+        assert!(matches!(
+            FlatTosm::open_verified(&path),
+            Err(TosmError::TruncatedPayload { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(FlatTosm::open(&path), Err(TosmError::Io(_))));
+    }
+}
@@ -0,0 +1,144 @@
+//! Small planar approximations used by the spatial queries. They treat the neighbourhood of
+//! a reference point as flat, which is accurate for the segment lengths found in road data.
+
+use crate::{dist_haversine, BoundingBox};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Length of a degree of latitude, or of longitude at the equator.
+pub(crate) const METRES_PER_DEGREE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+/// Great-circle distance in metres between two `[lat, lon]` points.
+pub(crate) fn distance_m(a: [f64; 2], b: [f64; 2]) -> f64 {
+    dist_haversine(&a, &b) * 1000.0
+}
+
+/// Longitude difference `b - a` wrapped into `[-180, 180)`.
+pub(crate) fn delta_lon(a: f64, b: f64) -> f64 {
+    (b - a + 540.0).rem_euclid(360.0) - 180.0
+}
+
+/// Local east/north offset of `p` from `origin`, in metres.
+pub(crate) fn to_plane(origin: [f64; 2], p: [f64; 2]) -> [f64; 2] {
+    let x = delta_lon(origin[1], p[1]).to_radians() * origin[0].to_radians().cos();
+    let y = (p[0] - origin[0]).to_radians();
+    [x * EARTH_RADIUS_M, y * EARTH_RADIUS_M]
+}
+
+/// Compass bearing from `a` to `b` in degrees, clockwise from north in `[0, 360)`.
+pub(crate) fn bearing(a: [f64; 2], b: [f64; 2]) -> f64 {
+    let [x, y] = to_plane(a, b);
+    x.atan2(y).to_degrees().rem_euclid(360.0)
+}
+
+/// Projects `p` onto the segment `a`-`b`, returning the position along the segment in `[0, 1]`
+/// and the projected point.
+pub(crate) fn project_onto_segment(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> (f64, [f64; 2]) {
+    let pa = to_plane(p, a);
+    let pb = to_plane(p, b);
+    let d = [pb[0] - pa[0], pb[1] - pa[1]];
+    let len2 = d[0] * d[0] + d[1] * d[1];
+
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (-(pa[0] * d[0] + pa[1] * d[1]) / len2).clamp(0.0, 1.0)
+    };
+
+    (t, interpolate(a, b, t))
+}
+
+/// The point at fraction `t` along the segment `a`-`b`.
+pub(crate) fn interpolate(a: [f64; 2], b: [f64; 2], t: f64) -> [f64; 2] {
+    let lat = a[0] + t * (b[0] - a[0]);
+    let lon = a[1] + t * delta_lon(a[1], b[1]);
+    [lat, (lon + 540.0).rem_euclid(360.0) - 180.0]
+}
+
+/// Rewrites the longitudes of a polyline so that consecutive points never differ by more than
+/// 180 degrees. A line crossing the antimeridian then continues past ±180 instead of jumping
+/// across the globe.
+pub(crate) fn unwrap_lons(coords: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let mut out: Vec<[f64; 2]> = Vec::with_capacity(coords.len());
+    for (i, c) in coords.iter().enumerate() {
+        let lon = match out.last() {
+            Some(prev) => prev[1] + delta_lon(coords[i - 1][1], c[1]),
+            None => c[1],
+        };
+        out.push([c[0], lon]);
+    }
+    out
+}
+
+/// Whether the segment `a`-`b` touches the box, using the Liang-Barsky clipping test in the
+/// lat/lon plane. `b` must already be unwrapped relative to `a`.
+pub(crate) fn segment_intersects_box(a: [f64; 2], b: [f64; 2], bbox: &BoundingBox) -> bool {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+
+    let edges = [
+        (-d[0], a[0] - bbox.min_lat),
+        (d[0], bbox.max_lat - a[0]),
+        (-d[1], a[1] - bbox.min_lon),
+        (d[1], bbox.max_lon - a[1]),
+    ];
+    for (p, q) in edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+            if t0 > t1 {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delta_lon, distance_m, project_onto_segment, segment_intersects_box};
+    use crate::BoundingBox;
+
+    #[test]
+    fn clips_segments_against_boxes() {
+        let bbox = BoundingBox {
+            min_lat: 0.0,
+            min_lon: 0.0,
+            max_lat: 1.0,
+            max_lon: 1.0,
+        };
+        assert!(segment_intersects_box([-1.0, 0.5], [2.0, 0.5], &bbox));
+        assert!(segment_intersects_box([0.5, 0.5], [0.6, 0.6], &bbox));
+        assert!(!segment_intersects_box([-1.0, 2.0], [2.0, 2.0], &bbox));
+        assert!(!segment_intersects_box([1.5, -0.5], [0.6, 2.6], &bbox));
+    }
+
+    #[test]
+    fn projects_onto_segments() {
+        let a = [64.0, -21.0];
+        let b = [64.0, -20.0];
+
+        let (t, p) = project_onto_segment([64.001, -20.5], a, b);
+        assert!((t - 0.5).abs() < 1e-9);
+        assert!((p[1] + 20.5).abs() < 1e-9);
+        assert!((distance_m([64.001, -20.5], p) - 111.2).abs() < 1.0);
+
+        let (t, p) = project_onto_segment([64.0, -22.0], a, b);
+        assert_eq!(t, 0.0);
+        assert_eq!(p, a);
+
+        assert_eq!(delta_lon(179.0, -179.0), 2.0);
+        let (t, _) = project_onto_segment([0.0, 180.0], [0.0, 179.0], [0.0, -179.0]);
+        assert!((t - 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,242 @@
+//! Convex and concave hulls of planar point sets.
+//!
+//! The concave hull follows the approach of Park and Oh (2012): start from the convex hull and
+//! repeatedly "dig" into long edges by replacing an edge `a-b` with `a-p-b`, where `p` is the
+//! closest interior point, as long as `p` lies close enough to the edge's ends and the outline
+//! stays simple. Candidate points are looked up in a k-d tree around the edge's ends.
+
+use kdtree::distance::squared_euclidean;
+use kdtree::KdTree;
+
+const NONE: usize = usize::MAX;
+
+/// Convex hull by Andrew's monotone chain, as point indexes in counter-clockwise order without
+/// collinear points.
+pub(crate) fn convex_hull(points: &[[f64; 2]]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| {
+        points[a][0]
+            .total_cmp(&points[b][0])
+            .then(points[a][1].total_cmp(&points[b][1]))
+    });
+    order.dedup_by(|a, b| points[*a] == points[*b]);
+    if order.len() < 3 {
+        return order;
+    }
+
+    let mut hull: Vec<usize> = vec![];
+    for pass in 0..2 {
+        let start = hull.len();
+        let chain: Box<dyn Iterator<Item = &usize>> = if pass == 0 {
+            Box::new(order.iter())
+        } else {
+            Box::new(order.iter().rev())
+        };
+        for &i in chain {
+            while hull.len() >= start + 2
+                && cross(
+                    points[hull[hull.len() - 2]],
+                    points[hull[hull.len() - 1]],
+                    points[i],
+                ) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(i);
+        }
+        // The last point of each chain is the first point of the other.
+        hull.pop();
+    }
+    hull
+}
+
+/// Concave hull as point indexes in counter-clockwise order. `concavity` controls how deep the
+/// outline follows the points: an edge is only split at a point whose distance to the nearer end
+/// is at most the edge length divided by `concavity`. Around 2 gives a tight outline, and
+/// `f64::INFINITY` returns the convex hull.
+pub(crate) fn concave_hull(points: &[[f64; 2]], concavity: f64) -> Vec<usize> {
+    let hull = convex_hull(points);
+    if hull.len() < 3 {
+        return hull;
+    }
+
+    let n = points.len();
+    let mut next = vec![NONE; n];
+    let mut prev = vec![NONE; n];
+    for (i, &a) in hull.iter().enumerate() {
+        let b = hull[(i + 1) % hull.len()];
+        next[a] = b;
+        prev[b] = a;
+    }
+    let on_hull = |next: &[usize], i: usize| next[i] != NONE;
+
+    let mut tree = KdTree::new(2);
+    for (i, point) in points.iter().enumerate() {
+        // Non-finite points can't be indexed and never become candidates.
+        let _ = tree.add(*point, i);
+    }
+
+    let mut queue: Vec<(usize, usize)> = hull
+        .iter()
+        .enumerate()
+        .map(|(i, &a)| (a, hull[(i + 1) % hull.len()]))
+        .collect();
+
+    while let Some((a, b)) = queue.pop() {
+        if next[a] != b {
+            continue;
+        }
+        let (pa, pb) = (points[a], points[b]);
+        let max_len2 = dist2(pa, pb) / (concavity * concavity);
+        let (before, after) = (points[prev[a]], points[next[b]]);
+
+        // Candidates lie within the allowed distance of one of the edge's ends. They are visited
+        // in index order so that ties go to the same point as in a full scan.
+        let mut nearby: Vec<usize> = [pa, pb]
+            .iter()
+            .flat_map(|end| {
+                tree.within(end, max_len2, &squared_euclidean)
+                    .unwrap_or_default()
+            })
+            .map(|(_, &p)| p)
+            .collect();
+        nearby.sort_unstable();
+        nearby.dedup();
+
+        // The interior point closest to this edge that isn't closer to a neighbouring edge.
+        let mut candidate: Option<(f64, usize)> = None;
+        for p in nearby {
+            let point = points[p];
+            if on_hull(&next, p) || point == pa || point == pb {
+                continue;
+            }
+            let d = segment_dist2(point, pa, pb);
+            if candidate.is_some_and(|(best, _)| best <= d)
+                || segment_dist2(point, before, pa) < d
+                || segment_dist2(point, pb, after) < d
+            {
+                continue;
+            }
+            candidate = Some((d, p));
+        }
+
+        let p = match candidate {
+            Some((_, p)) => p,
+            None => continue,
+        };
+        if crosses_outline(points, &next, a, b, points[p]) {
+            continue;
+        }
+
+        next[a] = p;
+        prev[p] = a;
+        next[p] = b;
+        prev[b] = p;
+        queue.push((a, p));
+        queue.push((p, b));
+    }
+
+    let start = hull[0];
+    let mut ring = vec![start];
+    let mut i = next[start];
+    while i != start {
+        ring.push(i);
+        i = next[i];
+    }
+    ring
+}
+
+/// Whether replacing the outline edge `a-b` with `a-p-b` makes the outline cross itself.
+fn crosses_outline(points: &[[f64; 2]], next: &[usize], a: usize, b: usize, p: [f64; 2]) -> bool {
+    let mut i = b;
+    while i != a {
+        let (q, r) = (points[i], points[next[i]]);
+        if segments_cross(points[a], p, q, r) || segments_cross(p, points[b], q, r) {
+            return true;
+        }
+        i = next[i];
+    }
+    false
+}
+
+/// Whether segments `a-b` and `c-d` intersect anywhere other than at a shared end point.
+fn segments_cross(a: [f64; 2], b: [f64; 2], c: [f64; 2], d: [f64; 2]) -> bool {
+    if a == c || a == d || b == c || b == d {
+        return false;
+    }
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+/// Z component of `(b - a) x (c - a)`, positive when `a`, `b`, `c` turn counter-clockwise.
+fn cross(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn dist2(a: [f64; 2], b: [f64; 2]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)
+}
+
+fn segment_dist2(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    let d = [b[0] - a[0], b[1] - a[1]];
+    let len2 = d[0] * d[0] + d[1] * d[1];
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (((p[0] - a[0]) * d[0] + (p[1] - a[1]) * d[1]) / len2).clamp(0.0, 1.0)
+    };
+    dist2(p, [a[0] + t * d[0], a[1] + t * d[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{concave_hull, convex_hull, segments_cross};
+
+    #[test]
+    fn digs_into_concave_shapes() {
+        // A U shape: two columns joined at the bottom, with the gap between them empty.
+        let mut points = vec![];
+        for y in 0..=10 {
+            points.push([0.0, y as f64]);
+            points.push([1.0, y as f64]);
+            points.push([4.0, y as f64]);
+            points.push([5.0, y as f64]);
+        }
+        points.push([2.0, 0.0]);
+        points.push([3.0, 0.0]);
+        points.push([2.0, 1.0]);
+        points.push([3.0, 1.0]);
+
+        let convex = convex_hull(&points);
+        assert_eq!(convex.len(), 4);
+
+        let concave = concave_hull(&points, 2.0);
+        assert!(concave.len() > 4);
+        assert!(concave.contains(&points.iter().position(|p| *p == [1.0, 10.0]).unwrap()));
+        assert!(concave.contains(&points.iter().position(|p| *p == [2.0, 1.0]).unwrap()));
+
+        assert_eq!(concave_hull(&points, f64::INFINITY).len(), 4);
+        assert_eq!(convex_hull(&[[0.0, 0.0], [1.0, 1.0], [2.0, 2.0]]).len(), 2);
+    }
+
+    #[test]
+    fn outlines_thousands_of_points() {
+        // A 60 by 60 grid with a notch cut into its right side.
+        let points: Vec<[f64; 2]> = (0..60)
+            .flat_map(|x| (0..60).map(move |y| [x as f64, y as f64]))
+            .filter(|&[x, y]| x < 30.0 || !(20.0..40.0).contains(&y))
+            .collect();
+        assert_eq!(points.len(), 3000);
+
+        let ring = concave_hull(&points, 2.0);
+        assert!(ring.iter().any(|&i| points[i] == [29.0, 30.0]));
+        for (i, &a) in ring.iter().enumerate() {
+            let b = ring[(i + 1) % ring.len()];
+            for (j, &c) in ring.iter().enumerate().skip(i + 1) {
+                let d = ring[(j + 1) % ring.len()];
+                assert!(!segments_cross(points[a], points[b], points[c], points[d]));
+            }
+        }
+    }
+}
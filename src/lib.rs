@@ -1,69 +1,804 @@
 use kdtree::KdTree;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Node {
+mod address;
+mod codec;
+mod container;
+mod error;
+mod flat;
+mod geo;
+mod hull;
+mod osm;
+mod pbf;
+mod query;
+mod routing;
+mod search;
+mod tags;
+mod xml;
+
+pub use address::{Address, AddressDistance};
+pub use codec::{Codec, Encoding, WriteOptions};
+pub use container::{BoundingBox, Header, FORMAT_VERSION};
+pub use error::TosmError;
+pub use flat::{FlatTosm, FlatWay};
+pub use query::{NamedWay, NodeDistance, WayMatch};
+pub use routing::{
+    DistanceMatrix, Instruction, Isochrone, Maneuver, MatchOptions, MatchedPoint, Profile,
+    ReachedNode, ReachedWay, Route, Router, TraceMatch,
+};
+pub use search::StreetMatch;
+pub use tags::{ImportOptions, MissingNodes, Tags};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Node {
     id: u64,
     lat: f64,
     lon: f64,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Way {
+impl Node {
+    pub fn new(id: u64, lat: f64, lon: f64) -> Self {
+        Node { id, lat, lon }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    pub fn lon(&self) -> f64 {
+        self.lon
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Way {
     id: u64,
     node_ids: Vec<u64>,
     one_way: bool,
     name: Option<String>,
+    /// Raw values of the OSM `highway`, `maxspeed` and `surface` tags, used by the routing
+    /// profiles. These routing attributes are derived from the tags on import and kept on the
+    /// way rather than read back from [`TOSMFile::way_tags`], as [`ImportOptions`] filters may
+    /// drop the tags while routing still needs them.
+    #[serde(default)]
+    highway: Option<String>,
+    #[serde(default)]
+    maxspeed: Option<String>,
+    #[serde(default)]
+    surface: Option<String>,
+    /// Whether cyclists may ride against `one_way` (`oneway:bicycle=no` or an opposite
+    /// cycleway).
+    #[serde(default)]
+    bicycle_contraflow: bool,
+    /// Whether the way is part of a roundabout (`junction=roundabout` or `circular`).
+    #[serde(default)]
+    roundabout: bool,
+    /// Raw values of the access tags (`access`, `vehicle`, `motor_vehicle`, `motorcar`,
+    /// `bicycle` and `foot`).
+    #[serde(default)]
+    access: Vec<(String, String)>,
+    /// Whether the way was built from OSM tags. Only untagged ways of JSON sources lack this,
+    /// and the routing profiles treat them as ordinary roads.
+    #[serde(default)]
+    tagged: bool,
+}
+
+impl Way {
+    pub fn new(id: u64, node_ids: Vec<u64>, one_way: bool, name: Option<String>) -> Self {
+        Way {
+            id,
+            node_ids,
+            one_way,
+            name,
+            highway: None,
+            maxspeed: None,
+            surface: None,
+            bicycle_contraflow: false,
+            roundabout: false,
+            access: vec![],
+            tagged: false,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn node_ids(&self) -> &[u64] {
+        &self.node_ids
+    }
+
+    pub fn one_way(&self) -> bool {
+        self.one_way
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn highway(&self) -> Option<&str> {
+        self.highway.as_deref()
+    }
+
+    pub fn maxspeed(&self) -> Option<&str> {
+        self.maxspeed.as_deref()
+    }
+
+    pub fn surface(&self) -> Option<&str> {
+        self.surface.as_deref()
+    }
+
+    pub fn bicycle_contraflow(&self) -> bool {
+        self.bicycle_contraflow
+    }
+
+    pub fn roundabout(&self) -> bool {
+        self.roundabout
+    }
+
+    /// The value of one of the access tags kept on the way, such as `access` or `bicycle`.
+    pub fn access(&self, key: &str) -> Option<&str> {
+        self.access
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn tagged(&self) -> bool {
+        self.tagged
+    }
+}
+
+/// The kind of element a relation member refers to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// A member of a relation. In source JSON: `{"type": "way", "ref": 10, "role": "outer"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Member {
+    #[serde(rename = "type")]
+    member_type: MemberType,
+    #[serde(rename = "ref")]
+    id: u64,
+    #[serde(default)]
+    role: String,
+}
+
+impl Member {
+    pub fn new(member_type: MemberType, id: u64, role: &str) -> Self {
+        Member {
+            member_type,
+            id,
+            role: role.to_string(),
+        }
+    }
+
+    pub fn member_type(&self) -> MemberType {
+        self.member_type
+    }
+
+    /// Id of the referenced node, way or relation.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The member's role, e.g. `outer`, `via` or `stop`. Empty when none is given.
+    pub fn role(&self) -> &str {
+        &self.role
+    }
+}
+
+/// An OSM relation, such as a multipolygon, a bus route or a turn restriction. What it
+/// represents is given by its tags, see [`TOSMFile::relation_tags`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Relation {
+    id: u64,
+    members: Vec<Member>,
+}
+
+impl Relation {
+    pub fn new(id: u64, members: Vec<Member>) -> Self {
+        Relation { id, members }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Members in their source order. Members may refer to elements missing from the file, as
+    /// relations in extracts often reach beyond the extract's bounds.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+}
+
+/// Whether a turn restriction forbids a manoeuvre (`no_*`) or makes it mandatory (`only_*`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    No,
+    Only,
+}
+
+/// What lies between the `from` and `to` ways of a turn restriction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Via {
+    Node(u64),
+    /// Ways in travel order, each travelled from end to end.
+    Ways(Vec<u64>),
+}
+
+/// A `type=restriction` relation as the router applies it. Relations with several `from` or `to`
+/// ways become one restriction per pair.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TurnRestriction {
+    relation_id: u64,
+    kind: RestrictionKind,
+    from_way: u64,
+    via: Via,
+    to_way: u64,
+    profiles: Vec<Profile>,
+}
+
+impl TurnRestriction {
+    pub fn relation_id(&self) -> u64 {
+        self.relation_id
+    }
+
+    pub fn kind(&self) -> RestrictionKind {
+        self.kind
+    }
+
+    pub fn from_way(&self) -> u64 {
+        self.from_way
+    }
+
+    pub fn via(&self) -> &Via {
+        &self.via
+    }
+
+    pub fn to_way(&self) -> u64 {
+        self.to_way
+    }
+
+    /// Whether the restriction binds travellers with `profile`, going by the `restriction:*`
+    /// key it was tagged with and its `except` tag. Pedestrians are never bound.
+    pub fn applies_to(&self, profile: Profile) -> bool {
+        self.profiles.contains(&profile)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct SourceFile {
-    nodes: Vec<Node>,
-    ways: Vec<Way>,
+    #[serde(default)]
+    timestamp: Option<i64>,
+    nodes: Vec<SourceElement<Node>>,
+    ways: Vec<SourceElement<SourceWay>>,
+    #[serde(default)]
+    relations: Vec<SourceElement<Relation>>,
+}
+
+/// A way in a source JSON document. The routing attributes of a [`Way`] are only derived from
+/// its `"tags"`.
+#[derive(Serialize, Deserialize, Debug)]
+struct SourceWay {
+    id: u64,
+    node_ids: Vec<u64>,
+    one_way: bool,
+    name: Option<String>,
 }
 
+/// An element in a source JSON document, with an optional `"tags"` object.
 #[derive(Serialize, Deserialize, Debug)]
-struct TOSMFile {
+struct SourceElement<T> {
+    #[serde(flatten)]
+    element: T,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TOSMFile {
+    source_timestamp: Option<i64>,
+
     nodes: Vec<Node>,
     ways: Vec<Way>,
+    relations: Vec<Relation>,
+    turn_restrictions: Vec<TurnRestriction>,
 
+    #[serde(skip)]
     node_indexes: HashMap<u64, usize>,
+    #[serde(skip)]
     way_indexes: HashMap<u64, usize>,
+    #[serde(skip)]
+    relation_indexes: HashMap<u64, usize>,
+    /// Indexes of the ways each node belongs to.
+    #[serde(skip)]
+    node_ways: HashMap<u64, Vec<usize>>,
+    // The indexes below are built on first use, so that loading a file only decodes it.
+    /// Points along every way, at the nodes and along long segments, with the way index. See
+    /// [`query::WAY_POINT_SPACING_M`].
+    #[serde(skip)]
+    way_points: OnceLock<KdTree<f64, u32, [f64; 2]>>,
+    #[serde(skip)]
+    street_index: OnceLock<search::StreetIndex>,
+    #[serde(skip)]
+    address_index: OnceLock<address::AddressIndex>,
+
+    tags: tags::TagStore,
 
     kd_tree: KdTree<f64, u64, [f64; 2]>,
+
+    contraction_hierarchies: Vec<routing::ContractionHierarchy>,
 }
 
-fn parse_file(path: &str) -> TOSMFile {
-    let mut file = TOSMFile {
-        nodes: vec![],
-        ways: vec![],
-        node_indexes: HashMap::new(),
-        way_indexes: HashMap::new(),
-        kd_tree: KdTree::new(2),
-    };
+impl TOSMFile {
+    /// Builds a file from a source JSON document on disk (`{"nodes": [...], "ways": [...]}`,
+    /// optionally with `"relations"`). Every element may carry a `"tags"` object of OSM tags.
+    /// Ways with tags are built from them as in OSM extracts, ignoring their `one_way` and
+    /// `name` fields.
+    pub fn from_json_path<P: AsRef<Path>>(path: P) -> Result<Self, TosmError> {
+        Self::from_json_path_with(path, &ImportOptions::default())
+    }
 
-    {
-        let source = std::fs::read_to_string(path).unwrap();
-        let v: SourceFile = serde_json::from_str(&source).unwrap();
+    pub fn from_json_path_with<P: AsRef<Path>>(
+        path: P,
+        options: &ImportOptions,
+    ) -> Result<Self, TosmError> {
+        let source = std::fs::read_to_string(path)?;
+        Self::from_json_str_with(&source, options)
+    }
+
+    /// Builds a file from a source JSON document.
+    pub fn from_json_str(source: &str) -> Result<Self, TosmError> {
+        Self::from_json_str_with(source, &ImportOptions::default())
+    }
+
+    pub fn from_json_str_with(source: &str, options: &ImportOptions) -> Result<Self, TosmError> {
+        let v: SourceFile = serde_json::from_str(source)?;
+
+        let mut tags = tags::TagStore::default();
+        let mut restrictions = vec![];
+        let nodes = v
+            .nodes
+            .into_iter()
+            .map(|n| {
+                tags.set_node(n.element.id, source_tags(&n.tags), options);
+                n.element
+            })
+            .collect();
+        let ways = v
+            .ways
+            .into_iter()
+            .map(|w| {
+                let SourceWay {
+                    id,
+                    node_ids,
+                    one_way,
+                    name,
+                } = w.element;
+                tags.set_way(id, source_tags(&w.tags), options);
+                if w.tags.is_empty() {
+                    Way::new(id, node_ids, one_way, name)
+                } else {
+                    osm::way_from_tags(id, node_ids, source_tags(&w.tags))
+                }
+            })
+            .collect();
+        let relations = v
+            .relations
+            .into_iter()
+            .map(|r| {
+                tags.set_relation(r.element.id, source_tags(&r.tags), options);
+                restrictions.extend(osm::restrictions_from_tags(
+                    &r.element,
+                    source_tags(&r.tags),
+                ));
+                r.element
+            })
+            .collect();
+
+        let mut file = Self::from_parts(
+            nodes,
+            ways,
+            relations,
+            restrictions,
+            tags,
+            options.missing_nodes,
+        )?;
+        file.source_timestamp = v.timestamp;
+        Ok(file)
+    }
+
+    /// Imports an OpenStreetMap `.osm.pbf` extract, deriving `one_way` and `name` from way tags.
+    pub fn from_pbf_path<P: AsRef<Path>>(path: P) -> Result<Self, TosmError> {
+        Self::from_pbf_path_with(path, &ImportOptions::default())
+    }
+
+    pub fn from_pbf_path_with<P: AsRef<Path>>(
+        path: P,
+        options: &ImportOptions,
+    ) -> Result<Self, TosmError> {
+        let in_file = std::fs::File::open(path)?;
+        Self::from_pbf_reader_with(std::io::BufReader::new(in_file), options)
+    }
+
+    pub fn from_pbf_reader<R: Read>(reader: R) -> Result<Self, TosmError> {
+        Self::from_pbf_reader_with(reader, &ImportOptions::default())
+    }
+
+    pub fn from_pbf_reader_with<R: Read>(
+        reader: R,
+        options: &ImportOptions,
+    ) -> Result<Self, TosmError> {
+        let contents = pbf::read_pbf(reader, options)?;
+        let mut file = Self::from_parts(
+            contents.nodes,
+            contents.ways,
+            contents.relations,
+            contents.restrictions,
+            contents.tags,
+            options.missing_nodes,
+        )?;
+        file.source_timestamp = contents.timestamp;
+        Ok(file)
+    }
+
+    /// Imports an OSM XML (`.osm`) or osmChange (`.osc`) document, e.g. a JOSM export.
+    pub fn from_xml_path<P: AsRef<Path>>(path: P) -> Result<Self, TosmError> {
+        Self::from_xml_path_with(path, &ImportOptions::default())
+    }
+
+    pub fn from_xml_path_with<P: AsRef<Path>>(
+        path: P,
+        options: &ImportOptions,
+    ) -> Result<Self, TosmError> {
+        let in_file = std::fs::File::open(path)?;
+        Self::from_xml_reader_with(std::io::BufReader::new(in_file), options)
+    }
+
+    pub fn from_xml_reader<R: BufRead>(reader: R) -> Result<Self, TosmError> {
+        Self::from_xml_reader_with(reader, &ImportOptions::default())
+    }
 
-        for node in v.nodes {
-            file.nodes.push(node.clone());
-            file.node_indexes.insert(node.id, file.nodes.len());
-            file.kd_tree.add([node.lat, node.lon], node.id).unwrap();
+    pub fn from_xml_reader_with<R: BufRead>(
+        reader: R,
+        options: &ImportOptions,
+    ) -> Result<Self, TosmError> {
+        let contents = xml::read_xml(reader, options)?;
+        let mut file = Self::from_parts(
+            contents.nodes,
+            contents.ways,
+            contents.relations,
+            contents.restrictions,
+            contents.tags,
+            options.missing_nodes,
+        )?;
+        file.source_timestamp = contents.timestamp;
+        Ok(file)
+    }
+
+    /// Reads a `.tosm` file previously written with [`TOSMFile::write_tosm`], verifying its
+    /// header and checksum.
+    pub fn from_tosm_reader<R: Read>(reader: R) -> Result<Self, TosmError> {
+        container::read(reader)
+    }
+
+    pub fn from_tosm_path<P: AsRef<Path>>(path: P) -> Result<Self, TosmError> {
+        let in_file = std::fs::File::open(path)?;
+        Self::from_tosm_reader(std::io::BufReader::new(in_file))
+    }
+
+    /// Reads only the header of a `.tosm` file.
+    pub fn read_tosm_header<R: Read>(mut reader: R) -> Result<Header, TosmError> {
+        container::read_header(&mut reader)
+    }
+
+    /// Writes the file in the `.tosm` container format with the default [`WriteOptions`].
+    pub fn write_tosm<W: Write>(&self, writer: W) -> Result<(), TosmError> {
+        self.write_tosm_with(writer, &WriteOptions::default())
+    }
+
+    pub fn write_tosm_with<W: Write>(
+        &self,
+        writer: W,
+        options: &WriteOptions,
+    ) -> Result<(), TosmError> {
+        container::write(self, writer, options)
+    }
+
+    pub fn save_tosm<P: AsRef<Path>>(&self, path: P) -> Result<(), TosmError> {
+        self.save_tosm_with(path, &WriteOptions::default())
+    }
+
+    pub fn save_tosm_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &WriteOptions,
+    ) -> Result<(), TosmError> {
+        let out_file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_tosm_with(out_file, options)
+    }
+
+    /// Writes the nodes and ways in the uncompressed flat layout, which [`FlatTosm`] queries
+    /// in place without decoding. Tags, relations and contraction hierarchies are left out,
+    /// and [`TOSMFile::from_tosm_reader`] does not read the result.
+    pub fn write_flat_tosm<W: Write>(&self, writer: W) -> Result<(), TosmError> {
+        flat::write(self, writer)
+    }
+
+    pub fn save_flat_tosm<P: AsRef<Path>>(&self, path: P) -> Result<(), TosmError> {
+        let out_file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_flat_tosm(out_file)
+    }
+
+    fn from_parts(
+        nodes: Vec<Node>,
+        ways: Vec<Way>,
+        relations: Vec<Relation>,
+        turn_restrictions: Vec<TurnRestriction>,
+        mut tags: tags::TagStore,
+        missing_nodes: MissingNodes,
+    ) -> Result<Self, TosmError> {
+        let mut ways = ways;
+        if missing_nodes != MissingNodes::Reject {
+            let node_ids: HashSet<u64> = nodes.iter().map(|n| n.id).collect();
+            ways.retain_mut(|way| {
+                let keep = match missing_nodes {
+                    MissingNodes::DropWays => way.node_ids.iter().all(|id| node_ids.contains(id)),
+                    _ => {
+                        let run = longest_run(&way.node_ids, &node_ids);
+                        let truncated = run.len() < way.node_ids.len();
+                        way.node_ids = run;
+                        !truncated || way.node_ids.len() >= 2
+                    }
+                };
+                if !keep {
+                    tags.set_way(way.id, std::iter::empty(), &ImportOptions::default());
+                }
+                keep
+            });
+        }
+
+        let mut kd_tree = KdTree::new(2);
+        for node in &nodes {
+            check_coordinate(Some(node.id), node.lat, node.lon)?;
+            kd_tree.add([node.lat, node.lon], node.id).map_err(|_| {
+                TosmError::InvalidCoordinate {
+                    node_id: Some(node.id),
+                    lat: node.lat,
+                    lon: node.lon,
+                }
+            })?;
         }
 
-        for way in v.ways {
-            file.ways.push(way.clone());
-            file.way_indexes.insert(way.id, file.ways.len());
+        let mut file = TOSMFile {
+            source_timestamp: None,
+            nodes,
+            ways,
+            relations,
+            turn_restrictions,
+            node_indexes: HashMap::new(),
+            way_indexes: HashMap::new(),
+            relation_indexes: HashMap::new(),
+            node_ways: HashMap::new(),
+            way_points: OnceLock::new(),
+            street_index: OnceLock::new(),
+            address_index: OnceLock::new(),
+            tags,
+            kd_tree,
+            contraction_hierarchies: vec![],
+        };
+        file.build_indexes();
+
+        for way in &file.ways {
+            if let Some(&node_id) = way
+                .node_ids
+                .iter()
+                .find(|id| !file.node_indexes.contains_key(id))
+            {
+                return Err(TosmError::DanglingNodeRef {
+                    way_id: way.id,
+                    node_id,
+                });
+            }
         }
+
+        Ok(file)
+    }
+
+    /// Rebuilds the id lookups, which are derived from the elements and not serialized, and
+    /// drops the lazily built indexes and hierarchy checks so they see the current elements.
+    pub(crate) fn build_indexes(&mut self) {
+        self.node_indexes = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id, i))
+            .collect();
+        self.way_indexes = self
+            .ways
+            .iter()
+            .enumerate()
+            .map(|(i, way)| (way.id, i))
+            .collect();
+        self.relation_indexes = self
+            .relations
+            .iter()
+            .enumerate()
+            .map(|(i, relation)| (relation.id, i))
+            .collect();
+
+        self.node_ways = HashMap::new();
+        for (i, way) in self.ways.iter().enumerate() {
+            for id in &way.node_ids {
+                let ways = self.node_ways.entry(*id).or_default();
+                if ways.last() != Some(&i) {
+                    ways.push(i);
+                }
+            }
+        }
+        self.way_points = OnceLock::new();
+        self.street_index = OnceLock::new();
+        self.address_index = OnceLock::new();
+        for ch in &mut self.contraction_hierarchies {
+            ch.recheck();
+        }
+    }
+
+    pub(crate) fn way_point_index(&self) -> &KdTree<f64, u32, [f64; 2]> {
+        self.way_points.get_or_init(|| {
+            let mut tree = KdTree::new(2);
+            for (i, way) in self.ways.iter().enumerate() {
+                for point in query::way_points(&self.way_coords(way)) {
+                    // Way coordinates come from checked nodes, which the tree accepts.
+                    let _ = tree.add(point, i as u32);
+                }
+            }
+            tree
+        })
+    }
+
+    pub(crate) fn street_index(&self) -> &search::StreetIndex {
+        self.street_index
+            .get_or_init(|| search::StreetIndex::new(self))
+    }
+
+    pub(crate) fn address_index(&self) -> &address::AddressIndex {
+        self.address_index
+            .get_or_init(|| address::AddressIndex::new(self))
+    }
+
+    /// Coordinates of the way's nodes, skipping ids missing from the file.
+    pub(crate) fn way_coords(&self, way: &Way) -> Vec<[f64; 2]> {
+        way.node_ids
+            .iter()
+            .filter_map(|id| self.node(*id).map(|n| [n.lat, n.lon]))
+            .collect()
+    }
+
+    /// Timestamp of the source data in unix seconds, when the source recorded one.
+    pub fn source_timestamp(&self) -> Option<i64> {
+        self.source_timestamp
+    }
+
+    /// The smallest box containing every node, or `None` for a file without nodes.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let first = self.nodes.first()?;
+        let mut bbox = BoundingBox {
+            min_lat: first.lat,
+            min_lon: first.lon,
+            max_lat: first.lat,
+            max_lon: first.lon,
+        };
+        for node in &self.nodes {
+            bbox.min_lat = bbox.min_lat.min(node.lat);
+            bbox.min_lon = bbox.min_lon.min(node.lon);
+            bbox.max_lat = bbox.max_lat.max(node.lat);
+            bbox.max_lon = bbox.max_lon.max(node.lon);
+        }
+        Some(bbox)
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn ways(&self) -> &[Way] {
+        &self.ways
+    }
+
+    pub fn relations(&self) -> &[Relation] {
+        &self.relations
+    }
+
+    /// The turn restrictions read from `type=restriction` relations at import, regardless of
+    /// which tags were kept.
+    pub fn turn_restrictions(&self) -> &[TurnRestriction] {
+        &self.turn_restrictions
+    }
+
+    pub fn node(&self, id: u64) -> Option<&Node> {
+        self.node_indexes.get(&id).map(|&i| &self.nodes[i])
+    }
+
+    pub fn way(&self, id: u64) -> Option<&Way> {
+        self.way_indexes.get(&id).map(|&i| &self.ways[i])
+    }
+
+    pub fn relation(&self, id: u64) -> Option<&Relation> {
+        self.relation_indexes.get(&id).map(|&i| &self.relations[i])
+    }
+
+    /// The OSM tags of a node that were kept at import. Empty for unknown ids.
+    pub fn node_tags(&self, id: u64) -> Tags<'_> {
+        self.tags.node(id)
+    }
+
+    /// The OSM tags of a way that were kept at import. Empty for unknown ids.
+    pub fn way_tags(&self, id: u64) -> Tags<'_> {
+        self.tags.way(id)
+    }
+
+    /// The OSM tags of a relation that were kept at import. Empty for unknown ids.
+    pub fn relation_tags(&self, id: u64) -> Tags<'_> {
+        self.tags.relation(id)
+    }
+
+    /// Returns the id of the node closest to the given coordinate, or `None` for an empty file.
+    pub fn nearest_node(&self, lat: f64, lon: f64) -> Result<Option<u64>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let res = self
+            .kd_tree
+            .nearest(&[lat, lon], 1, &dist_haversine)
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        Ok(res.first().map(|(_, id)| **id))
     }
+}
 
-    file
+fn source_tags(tags: &HashMap<String, String>) -> impl Iterator<Item = (&str, &str)> {
+    let mut tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (&**k, &**v)).collect();
+    // JSON objects are unordered; sorting keeps equal tag sets equal.
+    tags.sort();
+    tags.into_iter()
 }
 
-fn dist_haversine(a: &[f64], b: &[f64]) -> f64 {
+/// The longest run of consecutive refs that are in `nodes`.
+fn longest_run(refs: &[u64], nodes: &HashSet<u64>) -> Vec<u64> {
+    refs.split(|id| !nodes.contains(id))
+        .max_by_key(|run| run.len())
+        .unwrap_or_default()
+        .to_vec()
+}
+
+fn check_coordinate(node_id: Option<u64>, lat: f64, lon: f64) -> Result<(), TosmError> {
+    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) {
+        Ok(())
+    } else {
+        Err(TosmError::InvalidCoordinate { node_id, lat, lon })
+    }
+}
+
+/// Great-circle distance in kilometres between two `[lat, lon]` points given in degrees.
+pub fn dist_haversine(a: &[f64], b: &[f64]) -> f64 {
     let lat1 = a[0].to_radians();
     let lon1 = a[1].to_radians();
 
@@ -76,22 +811,21 @@ fn dist_haversine(a: &[f64], b: &[f64]) -> f64 {
     let sqrth =
         (dlathalf.sin().powi(2) + (lat1.cos() * lat2.cos() * dlonhalf.sin().powi(2))).sqrt();
 
-    return sqrth.asin() * 2.0 * 6371.0;
+    sqrth.asin() * 2.0 * 6371.0
 }
 
 #[cfg(test)]
 mod tests {
-    use std::fs::File;
-
-    use crate::{dist_haversine, parse_file, TOSMFile};
+    use crate::{
+        dist_haversine, ImportOptions, Member, MemberType, MissingNodes, Profile, Router, TOSMFile,
+        TosmError, Way,
+    };
 
     #[test]
     fn finds_fjolugata() {
-        let file = parse_file("out.json");
+        let file = TOSMFile::from_json_path("out.json").unwrap();
 
-        let out_file = File::create("iceland.tosm.br").unwrap();
-        let compressor = brotli::CompressorWriter::new(out_file, 4096, 4, 21);
-        bincode::serialize_into(compressor, &file).unwrap();
+        file.save_tosm("iceland.tosm.br").unwrap();
 
         let res = file
             .kd_tree
@@ -105,9 +839,7 @@ mod tests {
 
     #[test]
     fn can_read_from_file() {
-        let in_file = File::open("iceland.tosm.br").unwrap();
-        let decompressor = brotli::Decompressor::new(in_file, 4096);
-        let file: TOSMFile = bincode::deserialize_from(decompressor).unwrap();
+        let file = TOSMFile::from_tosm_path("iceland.tosm.br").unwrap();
 
         let res = file
             .kd_tree
@@ -118,4 +850,300 @@ mod tests {
 
         assert_eq!(result, &35618126)
     }
+
+    #[test]
+    fn round_trips_through_tosm() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": "Fjólugata"}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let mut blob = vec![];
+        file.write_tosm(&mut blob).unwrap();
+        let file = TOSMFile::from_tosm_reader(&blob[..]).unwrap();
+        assert!(file.way_points.get().is_none());
+        assert!(file.street_index.get().is_none());
+
+        assert_eq!(file.nodes().len(), 2);
+        assert_eq!(file.ways()[0].name(), Some("Fjólugata"));
+        assert_eq!(file.nearest_node(64.1431, -21.9391).unwrap(), Some(2));
+        assert_eq!(file.search_streets("fjól", 1).len(), 1);
+        assert!(file.street_index.get().is_some());
+    }
+
+    #[test]
+    fn imports_pbf() {
+        let data = crate::pbf::tests::encode(
+            &[(1, 64.1420, -21.9380), (2, 64.1430, -21.9390)],
+            (10, &[("name", "Fjólugata")]),
+        );
+        let file = TOSMFile::from_pbf_reader(&data[..]).unwrap();
+
+        assert_eq!(file.ways()[0].name(), Some("Fjólugata"));
+        assert_eq!(file.nearest_node(64.1431, -21.9391).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn looks_up_by_id() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390},
+                {"id": 3, "lat": 64.1440, "lon": -21.9400}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null},
+                {"id": 11, "node_ids": [2, 3], "one_way": true, "name": "Fjólugata"}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let mut blob = vec![];
+        file.write_tosm(&mut blob).unwrap();
+        let loaded = TOSMFile::from_tosm_reader(&blob[..]).unwrap();
+
+        for file in [file, loaded] {
+            assert_eq!(file.node(1).unwrap().lat(), 64.1420);
+            assert_eq!(file.node(3).unwrap().id(), 3);
+            assert!(file.node(4).is_none());
+            assert_eq!(file.way(10).unwrap().node_ids(), &[1, 2]);
+            assert_eq!(file.way(11).unwrap().name(), Some("Fjólugata"));
+            assert!(file.way(12).is_none());
+        }
+    }
+
+    #[test]
+    fn keeps_tags() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380,
+                 "tags": {"addr:street": "Fjólugata", "addr:housenumber": "5", "fixme": "check"}},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": "Fjólugata",
+                 "tags": {"highway": "residential", "name": "Fjólugata"}}
+            ]
+        }"#;
+        let options = ImportOptions {
+            include_tags: None,
+            exclude_tags: vec!["fixme".into()],
+            ..ImportOptions::default()
+        };
+        let file = TOSMFile::from_json_str_with(source, &options).unwrap();
+
+        let mut blob = vec![];
+        file.write_tosm(&mut blob).unwrap();
+        let loaded = TOSMFile::from_tosm_reader(&blob[..]).unwrap();
+
+        for file in [file, loaded] {
+            let tags = file.node_tags(1);
+            assert_eq!(tags.len(), 2);
+            assert_eq!(tags.get("addr:housenumber"), Some("5"));
+            assert_eq!(tags.get("fixme"), None);
+            assert!(file.node_tags(2).is_empty());
+            assert_eq!(file.way_tags(10).get("highway"), Some("residential"));
+            assert!(file.way_tags(11).is_empty());
+        }
+    }
+
+    #[test]
+    fn builds_ways_from_tags_like_osm_extracts() {
+        let json = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null,
+                 "tags": {"highway": "primary", "oneway": "-1", "name": "Hringbraut",
+                     "access": "no", "bus": "yes"}}
+            ]
+        }"#;
+        let xml = r#"<osm>
+            <node id="1" lat="64.1420" lon="-21.9380"/>
+            <node id="2" lat="64.1430" lon="-21.9390"/>
+            <way id="10">
+                <nd ref="1"/><nd ref="2"/>
+                <tag k="highway" v="primary"/><tag k="oneway" v="-1"/>
+                <tag k="name" v="Hringbraut"/><tag k="access" v="no"/><tag k="bus" v="yes"/>
+            </way>
+        </osm>"#;
+        let from_json = TOSMFile::from_json_str(json).unwrap();
+        let from_xml = TOSMFile::from_xml_reader(xml.as_bytes()).unwrap();
+
+        let way = from_json.way(10).unwrap();
+        assert_eq!(way, from_xml.way(10).unwrap());
+        assert_eq!(way.node_ids(), &[2, 1]);
+        assert!(way.one_way());
+        assert_eq!(way.name(), Some("Hringbraut"));
+        assert_eq!(way.access("access"), Some("no"));
+
+        // Routing attributes come from tags only, never from fields of the JSON way.
+        let untagged = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null,
+                 "highway": "motorway", "roundabout": true, "tagged": true}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(untagged).unwrap();
+        assert_eq!(
+            file.way(10).unwrap(),
+            &Way::new(10, vec![1, 2], false, None)
+        );
+    }
+
+    #[test]
+    fn routes_on_ways_whose_tags_were_dropped() {
+        let json = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null,
+                 "tags": {"highway": "footway"}}
+            ]
+        }"#;
+        let options = ImportOptions {
+            include_tags: Some(vec![]),
+            ..ImportOptions::default()
+        };
+        let file = TOSMFile::from_json_str_with(json, &options).unwrap();
+
+        assert!(file.way_tags(10).is_empty());
+        assert_eq!(file.way(10).unwrap().highway(), Some("footway"));
+        assert!(Router::with_profile(&file, Profile::Foot)
+            .route(1, 2)
+            .unwrap()
+            .is_some());
+        assert!(Router::new(&file).route(1, 2).is_err());
+    }
+
+    #[test]
+    fn reads_relations() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9380},
+                {"id": 2, "lat": 64.1430, "lon": -21.9390},
+                {"id": 3, "lat": 64.1440, "lon": -21.9400}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null},
+                {"id": 11, "node_ids": [2, 3], "one_way": false, "name": null}
+            ],
+            "relations": [
+                {"id": 20, "tags": {"type": "restriction", "restriction": "no_left_turn"},
+                 "members": [
+                    {"type": "way", "ref": 10, "role": "from"},
+                    {"type": "node", "ref": 2, "role": "via"},
+                    {"type": "way", "ref": 11, "role": "to"}
+                 ]},
+                {"id": 21, "tags": {"type": "route", "route": "bus"},
+                 "members": [{"type": "relation", "ref": 20}, {"type": "way", "ref": 99}]}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let mut blob = vec![];
+        file.write_tosm(&mut blob).unwrap();
+        let loaded = TOSMFile::from_tosm_reader(&blob[..]).unwrap();
+
+        for file in [file, loaded] {
+            assert_eq!(file.relations().len(), 2);
+            let restriction = file.relation(20).unwrap();
+            assert_eq!(
+                restriction.members()[1],
+                Member::new(MemberType::Node, 2, "via")
+            );
+            assert_eq!(
+                file.relation_tags(20).get("restriction"),
+                Some("no_left_turn")
+            );
+
+            let route = file.relation(21).unwrap();
+            assert_eq!(route.members()[0].member_type(), MemberType::Relation);
+            assert_eq!(route.members()[0].role(), "");
+            assert_eq!(route.members()[1].id(), 99);
+            assert!(file.relation(22).is_none());
+        }
+
+        let without = TOSMFile::from_json_str(r#"{"nodes": [], "ways": []}"#).unwrap();
+        assert!(without.relations().is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_sources() {
+        let out_of_range = r#"{"nodes": [{"id": 1, "lat": 91.0, "lon": 0.0}], "ways": []}"#;
+        assert!(matches!(
+            TOSMFile::from_json_str(out_of_range),
+            Err(TosmError::InvalidCoordinate {
+                node_id: Some(1),
+                ..
+            })
+        ));
+
+        let dangling = r#"{
+            "nodes": [{"id": 1, "lat": 64.0, "lon": -21.0}],
+            "ways": [{"id": 10, "node_ids": [1, 2], "one_way": false, "name": null}]
+        }"#;
+        assert!(matches!(
+            TOSMFile::from_json_str(dangling),
+            Err(TosmError::DanglingNodeRef {
+                way_id: 10,
+                node_id: 2
+            })
+        ));
+
+        // Ways crossing the border of a clipped extract.
+        let clipped = r#"<osm>
+            <node id="1" lat="64.0" lon="-21.0"/>
+            <node id="2" lat="64.1" lon="-21.0"/>
+            <node id="3" lat="64.2" lon="-21.0"/>
+            <way id="10"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/></way>
+            <way id="11"><nd ref="4"/><nd ref="1"/><tag k="highway" v="service"/></way>
+            <way id="12"><nd ref="1"/><nd ref="3"/></way>
+        </osm>"#;
+        let import = |missing_nodes| {
+            let options = ImportOptions {
+                missing_nodes,
+                ..ImportOptions::default()
+            };
+            TOSMFile::from_xml_reader_with(clipped.as_bytes(), &options)
+        };
+        assert!(matches!(
+            import(MissingNodes::Reject),
+            Err(TosmError::DanglingNodeRef {
+                way_id: 10,
+                node_id: 4
+            })
+        ));
+        let dropped = import(MissingNodes::DropWays).unwrap();
+        let ids: Vec<u64> = dropped.ways().iter().map(|w| w.id()).collect();
+        assert_eq!(ids, [12]);
+        let truncated = import(MissingNodes::Truncate).unwrap();
+        let ids: Vec<u64> = truncated.ways().iter().map(|w| w.id()).collect();
+        assert_eq!(ids, [10, 12]);
+        assert_eq!(truncated.way(10).unwrap().node_ids(), &[1, 2, 3]);
+        assert!(truncated.way_tags(11).is_empty());
+
+        assert!(matches!(
+            TOSMFile::from_json_path("does-not-exist.json"),
+            Err(TosmError::Io(_))
+        ));
+        assert!(matches!(
+            TOSMFile::from_tosm_reader(&b"garbage"[..]),
+            Err(TosmError::NotATosmFile)
+        ));
+    }
 }
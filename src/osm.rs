@@ -0,0 +1,172 @@
+//! Interpretation of raw OSM tags shared by the importers.
+
+use crate::{MemberType, Profile, Relation, RestrictionKind, TurnRestriction, Via, Way};
+
+/// Builds a [`Way`] from its OSM id, node refs and tags, deriving `one_way` and `name` and
+/// keeping the tags the routing profiles look at.
+///
+/// `oneway=-1` ways are stored reversed so that `one_way` always means "in node order".
+pub(crate) fn way_from_tags<'a, I>(id: u64, mut node_ids: Vec<u64>, tags: I) -> Way
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut name = None;
+    let mut oneway = None;
+    let mut roundabout = false;
+    let mut highway = None;
+    let mut maxspeed = None;
+    let mut surface = None;
+    let mut bicycle_contraflow = false;
+    let mut access = vec![];
+
+    for (key, value) in tags {
+        match key {
+            "name" => name = Some(value.to_string()),
+            "oneway" => oneway = Some(value),
+            "junction" => roundabout = value == "roundabout" || value == "circular",
+            "highway" => highway = Some(value.to_string()),
+            "maxspeed" => maxspeed = Some(value.to_string()),
+            "surface" => surface = Some(value.to_string()),
+            "oneway:bicycle" => bicycle_contraflow |= value == "no",
+            "cycleway" | "cycleway:left" | "cycleway:right" | "cycleway:both" => {
+                bicycle_contraflow |= value.starts_with("opposite")
+            }
+            "access" | "vehicle" | "motor_vehicle" | "motorcar" | "bicycle" | "foot" => {
+                access.push((key.to_string(), value.to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    let one_way = match oneway {
+        Some("yes") | Some("true") | Some("1") => true,
+        Some("-1") | Some("reverse") => {
+            node_ids.reverse();
+            true
+        }
+        Some(_) => false,
+        None => roundabout,
+    };
+
+    Way {
+        highway,
+        maxspeed,
+        surface,
+        bicycle_contraflow,
+        roundabout,
+        access,
+        tagged: true,
+        ..Way::new(id, node_ids, one_way, name)
+    }
+}
+
+/// Reads the turn restrictions of a `type=restriction` relation. Each `restriction` or
+/// `restriction:<vehicle>` key yields restrictions for the profiles it covers, less those
+/// named in `except`. Relations without a `from` way, a `to` way or a single kind of `via`
+/// yield none.
+pub(crate) fn restrictions_from_tags<'a, I>(relation: &Relation, tags: I) -> Vec<TurnRestriction>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut is_restriction = false;
+    let mut kinds: Vec<(RestrictionKind, Vec<Profile>)> = vec![];
+    let mut except: Vec<&str> = vec![];
+    for (key, value) in tags {
+        let profiles = match key {
+            "type" => {
+                is_restriction = value == "restriction";
+                continue;
+            }
+            "except" => {
+                except = value.split(';').map(str::trim).collect();
+                continue;
+            }
+            "restriction" | "restriction:vehicle" => vec![Profile::Car, Profile::Bicycle],
+            "restriction:motor_vehicle" | "restriction:motorcar" => vec![Profile::Car],
+            "restriction:bicycle" => vec![Profile::Bicycle],
+            _ => continue,
+        };
+        let kind = if value.starts_with("no_") {
+            RestrictionKind::No
+        } else if value.starts_with("only_") {
+            RestrictionKind::Only
+        } else {
+            continue;
+        };
+        kinds.push((kind, profiles));
+    }
+    if !is_restriction {
+        return vec![];
+    }
+
+    let members = |member_type: MemberType, role: &str| -> Vec<u64> {
+        relation
+            .members()
+            .iter()
+            .filter(|m| m.member_type() == member_type && m.role() == role)
+            .map(|m| m.id())
+            .collect()
+    };
+    let from = members(MemberType::Way, "from");
+    let to = members(MemberType::Way, "to");
+    let via = match (
+        &members(MemberType::Node, "via")[..],
+        members(MemberType::Way, "via"),
+    ) {
+        (&[node], ways) if ways.is_empty() => Via::Node(node),
+        (&[], ways) if !ways.is_empty() => Via::Ways(ways),
+        _ => return vec![],
+    };
+
+    let mut restrictions = vec![];
+    for (kind, mut profiles) in kinds {
+        profiles.retain(|profile| {
+            let excepted = match profile {
+                Profile::Car => ["motorcar", "motor_vehicle"].as_slice(),
+                Profile::Bicycle => ["bicycle"].as_slice(),
+                Profile::Foot => [].as_slice(),
+            };
+            !except.iter().any(|e| excepted.contains(e))
+        });
+        if profiles.is_empty() {
+            continue;
+        }
+        for &from_way in &from {
+            for &to_way in &to {
+                restrictions.push(TurnRestriction {
+                    relation_id: relation.id(),
+                    kind,
+                    from_way,
+                    via: via.clone(),
+                    to_way,
+                    profiles: profiles.clone(),
+                });
+            }
+        }
+    }
+    restrictions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::way_from_tags;
+
+    #[test]
+    fn derives_one_way_and_name() {
+        let way = way_from_tags(1, vec![1, 2, 3], [("name", "Fjólugata"), ("oneway", "-1")]);
+        assert!(way.one_way());
+        assert_eq!(way.node_ids(), &[3, 2, 1]);
+        assert_eq!(way.name(), Some("Fjólugata"));
+
+        let way = way_from_tags(2, vec![1, 2], [("junction", "roundabout")]);
+        assert!(way.one_way());
+        assert!(way.roundabout());
+
+        let way = way_from_tags(
+            3,
+            vec![1, 2],
+            [("junction", "roundabout"), ("oneway", "no")],
+        );
+        assert!(!way.one_way());
+    }
+}
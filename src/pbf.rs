@@ -0,0 +1,702 @@
+//! Reader for the OSM PBF format (`.osm.pbf`).
+//!
+//! Only the parts of the format tosm needs are decoded: the `OSMHeader` block is checked for
+//! unsupported required features, and `OSMData` blocks yield nodes (plain and dense), ways and
+//! relations together with their tags.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::osm::{restrictions_from_tags, way_from_tags};
+use crate::tags::TagStore;
+use crate::{ImportOptions, Member, MemberType, Node, Relation, TosmError, TurnRestriction, Way};
+
+const MAX_BLOB_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+const SUPPORTED_FEATURES: &[&str] = &["OsmSchema-V0.6", "DenseNodes"];
+
+#[derive(Default)]
+pub(crate) struct PbfContents {
+    pub timestamp: Option<i64>,
+    pub nodes: Vec<Node>,
+    pub ways: Vec<Way>,
+    pub relations: Vec<Relation>,
+    pub restrictions: Vec<TurnRestriction>,
+    pub tags: TagStore,
+}
+
+pub(crate) fn read_pbf<R: Read>(
+    mut reader: R,
+    options: &ImportOptions,
+) -> Result<PbfContents, TosmError> {
+    let mut contents = PbfContents::default();
+    let mut seen_header = false;
+
+    while let Some((blob_type, data)) = read_blob(&mut reader)? {
+        match blob_type.as_str() {
+            "OSMHeader" => {
+                contents.timestamp = read_header_block(&data)?;
+                seen_header = true;
+            }
+            "OSMData" if seen_header => read_primitive_block(&data, &mut contents, options)?,
+            "OSMData" => return Err(invalid("OSMData blob before OSMHeader")),
+            // Unknown blob types must be skipped according to the spec.
+            _ => {}
+        }
+    }
+
+    Ok(contents)
+}
+
+fn invalid(message: &str) -> TosmError {
+    TosmError::InvalidPbf(message.to_string())
+}
+
+/// Reads the next `BlobHeader`/`Blob` pair and returns its type and decompressed payload.
+fn read_blob<R: Read>(reader: &mut R) -> Result<Option<(String, Vec<u8>)>, TosmError> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let header_len = u32::from_be_bytes(len) as usize;
+    if header_len > MAX_BLOB_HEADER_SIZE {
+        return Err(invalid("blob header too large"));
+    }
+    let mut header = vec![0; header_len];
+    reader.read_exact(&mut header)?;
+
+    let mut blob_type = None;
+    let mut data_size = None;
+    for field in Fields::new(&header) {
+        match field? {
+            (1, Value::Bytes(b)) => blob_type = Some(utf8(b)?.to_string()),
+            (3, Value::Varint(v)) => data_size = Some(v as usize),
+            _ => {}
+        }
+    }
+    let blob_type = blob_type.ok_or_else(|| invalid("blob header without type"))?;
+    let data_size = data_size.ok_or_else(|| invalid("blob header without datasize"))?;
+    if data_size > MAX_BLOB_SIZE {
+        return Err(invalid("blob too large"));
+    }
+
+    let mut blob = vec![0; data_size];
+    reader.read_exact(&mut blob)?;
+
+    let mut raw_size = None;
+    let mut data = None;
+    for field in Fields::new(&blob) {
+        match field? {
+            (1, Value::Bytes(b)) => data = Some(b.to_vec()),
+            (2, Value::Varint(v)) => raw_size = Some(v as usize),
+            (3, Value::Bytes(b)) => {
+                // Never inflate past the declared size, so a small crafted blob cannot expand
+                // into gigabytes.
+                let limit = raw_size.unwrap_or(MAX_BLOB_SIZE).min(MAX_BLOB_SIZE);
+                let mut out = Vec::with_capacity(limit);
+                ZlibDecoder::new(b)
+                    .take(limit as u64 + 1)
+                    .read_to_end(&mut out)?;
+                if out.len() > limit {
+                    return Err(invalid("blob inflates past its raw size"));
+                }
+                data = Some(out);
+            }
+            (4..=7, _) => return Err(invalid("unsupported blob compression")),
+            _ => {}
+        }
+    }
+
+    let data = data.ok_or_else(|| invalid("blob without data"))?;
+    Ok(Some((blob_type, data)))
+}
+
+/// Checks the required features of an `OSMHeader` block and returns its replication timestamp.
+fn read_header_block(data: &[u8]) -> Result<Option<i64>, TosmError> {
+    let mut timestamp = None;
+    for field in Fields::new(data) {
+        match field? {
+            (4, Value::Bytes(b)) => {
+                let feature = utf8(b)?;
+                if !SUPPORTED_FEATURES.contains(&feature) {
+                    return Err(TosmError::InvalidPbf(format!(
+                        "unsupported required feature {}",
+                        feature
+                    )));
+                }
+            }
+            (32, Value::Varint(v)) => timestamp = Some(v as i64),
+            _ => {}
+        }
+    }
+
+    Ok(timestamp)
+}
+
+struct Block<'a> {
+    strings: Vec<&'a str>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl<'a> Block<'a> {
+    fn string(&self, index: u64) -> Result<&'a str, TosmError> {
+        self.strings
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| invalid("string table index out of range"))
+    }
+
+    fn lat(&self, raw: i64) -> f64 {
+        1e-9 * (self.lat_offset + self.granularity * raw) as f64
+    }
+
+    fn lon(&self, raw: i64) -> f64 {
+        1e-9 * (self.lon_offset + self.granularity * raw) as f64
+    }
+}
+
+fn read_primitive_block(
+    data: &[u8],
+    contents: &mut PbfContents,
+    options: &ImportOptions,
+) -> Result<(), TosmError> {
+    let mut block = Block {
+        strings: vec![],
+        granularity: 100,
+        lat_offset: 0,
+        lon_offset: 0,
+    };
+    let mut groups = vec![];
+
+    for field in Fields::new(data) {
+        match field? {
+            (1, Value::Bytes(table)) => {
+                for field in Fields::new(table) {
+                    if let (1, Value::Bytes(s)) = field? {
+                        block.strings.push(utf8(s)?);
+                    }
+                }
+            }
+            (2, Value::Bytes(group)) => groups.push(group),
+            (17, Value::Varint(v)) => block.granularity = v as i64,
+            (19, Value::Varint(v)) => block.lat_offset = v as i64,
+            (20, Value::Varint(v)) => block.lon_offset = v as i64,
+            _ => {}
+        }
+    }
+
+    for group in groups {
+        for field in Fields::new(group) {
+            match field? {
+                (1, Value::Bytes(node)) => {
+                    let (node, tags) = read_node(&block, node)?;
+                    contents.tags.set_node(node.id(), tags, options);
+                    contents.nodes.push(node);
+                }
+                (2, Value::Bytes(dense)) => read_dense_nodes(&block, dense, contents, options)?,
+                (3, Value::Bytes(way)) => {
+                    let (way, tags) = read_way(&block, way)?;
+                    contents.tags.set_way(way.id(), tags, options);
+                    contents.ways.push(way);
+                }
+                (4, Value::Bytes(relation)) => {
+                    let (relation, tags) = read_relation(&block, relation)?;
+                    contents
+                        .tags
+                        .set_relation(relation.id(), tags.iter().copied(), options);
+                    contents
+                        .restrictions
+                        .extend(restrictions_from_tags(&relation, tags));
+                    contents.relations.push(relation);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+type Tags<'a> = Vec<(&'a str, &'a str)>;
+
+fn read_node<'a>(block: &Block<'a>, data: &[u8]) -> Result<(Node, Tags<'a>), TosmError> {
+    let (mut id, mut lat, mut lon) = (0, 0, 0);
+    let (mut keys, mut vals) = (vec![], vec![]);
+    for field in Fields::new(data) {
+        match field? {
+            (1, Value::Varint(v)) => id = zigzag(v),
+            (2, Value::Bytes(b)) => keys = packed_uint(b)?,
+            (3, Value::Bytes(b)) => vals = packed_uint(b)?,
+            (8, Value::Varint(v)) => lat = zigzag(v),
+            (9, Value::Varint(v)) => lon = zigzag(v),
+            _ => {}
+        }
+    }
+
+    let node = Node::new(id as u64, block.lat(lat), block.lon(lon));
+    Ok((node, read_tags(block, keys, vals)?))
+}
+
+fn read_dense_nodes(
+    block: &Block,
+    data: &[u8],
+    contents: &mut PbfContents,
+    options: &ImportOptions,
+) -> Result<(), TosmError> {
+    let (mut ids, mut lats, mut lons, mut keys_vals) = (vec![], vec![], vec![], vec![]);
+    for field in Fields::new(data) {
+        match field? {
+            (1, Value::Bytes(b)) => ids = packed_sint(b)?,
+            (8, Value::Bytes(b)) => lats = packed_sint(b)?,
+            (9, Value::Bytes(b)) => lons = packed_sint(b)?,
+            (10, Value::Bytes(b)) => keys_vals = packed_uint(b)?,
+            _ => {}
+        }
+    }
+
+    if ids.len() != lats.len() || ids.len() != lons.len() {
+        return Err(invalid("dense node arrays differ in length"));
+    }
+
+    // `keys_vals` holds key and value string indexes for each node in turn, each node's tags
+    // ending with a 0. It is empty when no node in the block has tags.
+    let mut keys_vals = keys_vals.into_iter();
+    let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+    for i in 0..ids.len() {
+        id += ids[i];
+        lat += lats[i];
+        lon += lons[i];
+
+        let mut tags = vec![];
+        while let Some(key) = keys_vals.next().filter(|&k| k != 0) {
+            let value = keys_vals
+                .next()
+                .ok_or_else(|| invalid("dense node tag without value"))?;
+            tags.push((block.string(key)?, block.string(value)?));
+        }
+        contents.tags.set_node(id as u64, tags, options);
+        contents
+            .nodes
+            .push(Node::new(id as u64, block.lat(lat), block.lon(lon)));
+    }
+
+    Ok(())
+}
+
+fn read_way<'a>(block: &Block<'a>, data: &[u8]) -> Result<(Way, Tags<'a>), TosmError> {
+    let mut id = 0;
+    let (mut keys, mut vals, mut refs) = (vec![], vec![], vec![]);
+    for field in Fields::new(data) {
+        match field? {
+            (1, Value::Varint(v)) => id = v,
+            (2, Value::Bytes(b)) => keys = packed_uint(b)?,
+            (3, Value::Bytes(b)) => vals = packed_uint(b)?,
+            (8, Value::Bytes(b)) => refs = packed_sint(b)?,
+            _ => {}
+        }
+    }
+    let tags = read_tags(block, keys, vals)?;
+
+    let mut node_id = 0i64;
+    let node_ids = refs
+        .into_iter()
+        .map(|delta| {
+            node_id += delta;
+            node_id as u64
+        })
+        .collect();
+
+    Ok((way_from_tags(id, node_ids, tags.iter().copied()), tags))
+}
+
+fn read_relation<'a>(block: &Block<'a>, data: &[u8]) -> Result<(Relation, Tags<'a>), TosmError> {
+    let mut id = 0;
+    let (mut keys, mut vals) = (vec![], vec![]);
+    let (mut roles, mut ids, mut types) = (vec![], vec![], vec![]);
+    for field in Fields::new(data) {
+        match field? {
+            (1, Value::Varint(v)) => id = v,
+            (2, Value::Bytes(b)) => keys = packed_uint(b)?,
+            (3, Value::Bytes(b)) => vals = packed_uint(b)?,
+            (8, Value::Bytes(b)) => roles = packed_uint(b)?,
+            (9, Value::Bytes(b)) => ids = packed_sint(b)?,
+            (10, Value::Bytes(b)) => types = packed_uint(b)?,
+            _ => {}
+        }
+    }
+    let tags = read_tags(block, keys, vals)?;
+
+    if roles.len() != ids.len() || types.len() != ids.len() {
+        return Err(invalid("relation member arrays differ in length"));
+    }
+    let mut member_id = 0i64;
+    let mut members = Vec::with_capacity(ids.len());
+    for i in 0..ids.len() {
+        member_id += ids[i];
+        let member_type = match types[i] {
+            0 => MemberType::Node,
+            1 => MemberType::Way,
+            2 => MemberType::Relation,
+            _ => return Err(invalid("unknown relation member type")),
+        };
+        members.push(Member::new(
+            member_type,
+            member_id as u64,
+            block.string(roles[i])?,
+        ));
+    }
+
+    Ok((Relation::new(id, members), tags))
+}
+
+/// Resolves parallel key and value string indexes.
+fn read_tags<'a>(block: &Block<'a>, keys: Vec<u64>, vals: Vec<u64>) -> Result<Tags<'a>, TosmError> {
+    if keys.len() != vals.len() {
+        return Err(invalid("keys and values differ in length"));
+    }
+    keys.into_iter()
+        .zip(vals)
+        .map(|(k, v)| Ok((block.string(k)?, block.string(v)?)))
+        .collect()
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, TosmError> {
+    std::str::from_utf8(bytes).map_err(|_| invalid("invalid utf-8 string"))
+}
+
+fn zigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn packed_uint(mut data: &[u8]) -> Result<Vec<u64>, TosmError> {
+    let mut out = vec![];
+    while !data.is_empty() {
+        out.push(varint(&mut data)?);
+    }
+    Ok(out)
+}
+
+fn packed_sint(data: &[u8]) -> Result<Vec<i64>, TosmError> {
+    Ok(packed_uint(data)?.into_iter().map(zigzag).collect())
+}
+
+fn varint(data: &mut &[u8]) -> Result<u64, TosmError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data
+            .split_first()
+            .ok_or_else(|| invalid("truncated varint"))?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterator over the `(field number, value)` pairs of an encoded protobuf message.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Fields { data }
+    }
+
+    fn next_field(&mut self) -> Result<(u32, Value<'a>), TosmError> {
+        let key = varint(&mut self.data)?;
+        let value = match key & 7 {
+            0 => Value::Varint(varint(&mut self.data)?),
+            1 => self.skip(8)?,
+            2 => {
+                let len = varint(&mut self.data)? as usize;
+                if len > self.data.len() {
+                    return Err(invalid("truncated field"));
+                }
+                let (bytes, rest) = self.data.split_at(len);
+                self.data = rest;
+                Value::Bytes(bytes)
+            }
+            5 => self.skip(4)?,
+            _ => return Err(invalid("unsupported wire type")),
+        };
+
+        Ok(((key >> 3) as u32, value))
+    }
+
+    fn skip(&mut self, len: usize) -> Result<Value<'a>, TosmError> {
+        if len > self.data.len() {
+            return Err(invalid("truncated field"));
+        }
+        self.data = &self.data[len..];
+        Ok(Value::Fixed)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>), TosmError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.next_field();
+        if field.is_err() {
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use super::read_pbf;
+    use crate::{ImportOptions, Member, MemberType, TosmError};
+
+    /// A relation to encode: id, `(type, ref, role)` members and tags.
+    pub(crate) type TestRelation<'a> = (
+        i64,
+        &'a [(MemberType, i64, &'a str)],
+        &'a [(&'a str, &'a str)],
+    );
+
+    fn varint(out: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            out.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn zigzag(v: i64) -> u64 {
+        ((v << 1) ^ (v >> 63)) as u64
+    }
+
+    fn uint_field(out: &mut Vec<u8>, field: u64, v: u64) {
+        varint(out, field << 3);
+        varint(out, v);
+    }
+
+    fn bytes_field(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+        varint(out, (field << 3) | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+
+    fn packed(values: impl IntoIterator<Item = u64>) -> Vec<u8> {
+        let mut out = vec![];
+        for v in values {
+            varint(&mut out, v);
+        }
+        out
+    }
+
+    fn deltas(values: &[i64]) -> Vec<u8> {
+        let mut prev = 0;
+        packed(values.iter().map(|&v| {
+            let delta = v - prev;
+            prev = v;
+            zigzag(delta)
+        }))
+    }
+
+    fn blob(out: &mut Vec<u8>, blob_type: &str, data: &[u8]) {
+        blob_with_raw_size(out, blob_type, data, data.len() as u64);
+    }
+
+    fn blob_with_raw_size(out: &mut Vec<u8>, blob_type: &str, data: &[u8], raw_size: u64) {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+
+        let mut blob = vec![];
+        uint_field(&mut blob, 2, raw_size);
+        bytes_field(&mut blob, 3, &encoder.finish().unwrap());
+
+        let mut header = vec![];
+        bytes_field(&mut header, 1, blob_type.as_bytes());
+        uint_field(&mut header, 3, blob.len() as u64);
+
+        out.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&blob);
+    }
+
+    /// Encodes a minimal PBF file: dense nodes at the given coordinates and a single way
+    /// referencing all of them with the given tags.
+    pub(crate) fn encode(nodes: &[(i64, f64, f64)], way: (i64, &[(&str, &str)])) -> Vec<u8> {
+        encode_tagged(nodes, &[], way, &[])
+    }
+
+    /// Like [`encode`], additionally tagging the nodes listed in `node_tags` by id and adding
+    /// relations.
+    pub(crate) fn encode_tagged(
+        nodes: &[(i64, f64, f64)],
+        node_tags: &[(i64, &[(&str, &str)])],
+        way: (i64, &[(&str, &str)]),
+        relations: &[TestRelation],
+    ) -> Vec<u8> {
+        let mut out = vec![];
+
+        let mut header = vec![];
+        bytes_field(&mut header, 4, b"OsmSchema-V0.6");
+        bytes_field(&mut header, 4, b"DenseNodes");
+        uint_field(&mut header, 32, 1650000000);
+        blob(&mut out, "OSMHeader", &header);
+
+        let mut table: Vec<&str> = vec![""];
+        let mut string = |s| match table.iter().position(|t| *t == s) {
+            Some(i) => i as u64,
+            None => {
+                table.push(s);
+                table.len() as u64 - 1
+            }
+        };
+
+        let mut keys_vals = vec![];
+        for node in nodes {
+            let tags = node_tags.iter().filter(|(id, _)| *id == node.0);
+            for &(k, v) in tags.flat_map(|(_, tags)| tags.iter()) {
+                keys_vals.push(string(k));
+                keys_vals.push(string(v));
+            }
+            keys_vals.push(0);
+        }
+        let way_keys: Vec<u64> = way.1.iter().map(|(k, _)| string(k)).collect();
+        let way_vals: Vec<u64> = way.1.iter().map(|(_, v)| string(v)).collect();
+
+        let mut encoded_relations = vec![];
+        for (id, members, tags) in relations {
+            let mut relation = vec![];
+            uint_field(&mut relation, 1, *id as u64);
+            let keys: Vec<u64> = tags.iter().map(|(k, _)| string(k)).collect();
+            let vals: Vec<u64> = tags.iter().map(|(_, v)| string(v)).collect();
+            bytes_field(&mut relation, 2, &packed(keys));
+            bytes_field(&mut relation, 3, &packed(vals));
+            let roles: Vec<u64> = members.iter().map(|m| string(m.2)).collect();
+            let refs: Vec<i64> = members.iter().map(|m| m.1).collect();
+            let types = members.iter().map(|m| match m.0 {
+                MemberType::Node => 0,
+                MemberType::Way => 1,
+                MemberType::Relation => 2,
+            });
+            bytes_field(&mut relation, 8, &packed(roles));
+            bytes_field(&mut relation, 9, &deltas(&refs));
+            bytes_field(&mut relation, 10, &packed(types));
+            encoded_relations.push(relation);
+        }
+
+        let mut strings = vec![];
+        for s in &table {
+            bytes_field(&mut strings, 1, s.as_bytes());
+        }
+
+        let ids: Vec<i64> = nodes.iter().map(|n| n.0).collect();
+        let lats: Vec<i64> = nodes.iter().map(|n| (n.1 * 1e7).round() as i64).collect();
+        let lons: Vec<i64> = nodes.iter().map(|n| (n.2 * 1e7).round() as i64).collect();
+        let mut dense = vec![];
+        bytes_field(&mut dense, 1, &deltas(&ids));
+        bytes_field(&mut dense, 8, &deltas(&lats));
+        bytes_field(&mut dense, 9, &deltas(&lons));
+        if !node_tags.is_empty() {
+            bytes_field(&mut dense, 10, &packed(keys_vals));
+        }
+
+        let mut encoded_way = vec![];
+        uint_field(&mut encoded_way, 1, way.0 as u64);
+        bytes_field(&mut encoded_way, 2, &packed(way_keys));
+        bytes_field(&mut encoded_way, 3, &packed(way_vals));
+        bytes_field(&mut encoded_way, 8, &deltas(&ids));
+
+        let mut group = vec![];
+        bytes_field(&mut group, 2, &dense);
+        bytes_field(&mut group, 3, &encoded_way);
+        for relation in &encoded_relations {
+            bytes_field(&mut group, 4, relation);
+        }
+
+        let mut block = vec![];
+        bytes_field(&mut block, 1, &strings);
+        bytes_field(&mut block, 2, &group);
+        blob(&mut out, "OSMData", &block);
+
+        out
+    }
+
+    #[test]
+    fn reads_dense_nodes_and_ways() {
+        let data = encode_tagged(
+            &[
+                (100, 64.1420, -21.9380),
+                (101, 64.1430, -21.9390),
+                (102, 64.1440, -21.9400),
+            ],
+            &[(101, &[("highway", "crossing"), ("crossing", "zebra")])],
+            (7, &[("name", "Fjólugata"), ("oneway", "yes")]),
+            &[(
+                20,
+                &[(MemberType::Way, 7, "outer"), (MemberType::Node, 101, "")],
+                &[("type", "multipolygon")],
+            )],
+        );
+        let contents = read_pbf(&data[..], &ImportOptions::default()).unwrap();
+
+        assert_eq!(contents.timestamp, Some(1650000000));
+        assert_eq!(contents.nodes.len(), 3);
+        assert_eq!(contents.nodes[1].id(), 101);
+        assert!((contents.nodes[1].lat() - 64.1430).abs() < 1e-7);
+        assert!((contents.nodes[1].lon() + 21.9390).abs() < 1e-7);
+
+        let way = &contents.ways[0];
+        assert_eq!(way.id(), 7);
+        assert_eq!(way.node_ids(), &[100, 101, 102]);
+        assert_eq!(way.name(), Some("Fjólugata"));
+        assert!(way.one_way());
+
+        assert!(contents.tags.node(100).is_empty());
+        assert_eq!(contents.tags.node(101).get("crossing"), Some("zebra"));
+        assert!(contents.tags.node(102).is_empty());
+        assert_eq!(contents.tags.way(7).get("oneway"), Some("yes"));
+
+        let relation = &contents.relations[0];
+        assert_eq!(relation.id(), 20);
+        assert_eq!(
+            relation.members(),
+            &[
+                Member::new(MemberType::Way, 7, "outer"),
+                Member::new(MemberType::Node, 101, ""),
+            ]
+        );
+        assert_eq!(contents.tags.relation(20).get("type"), Some("multipolygon"));
+    }
+
+    #[test]
+    fn rejects_blobs_inflating_past_their_raw_size() {
+        let mut data = vec![];
+        blob_with_raw_size(&mut data, "OSMData", &vec![0; 1 << 20], 100);
+
+        match read_pbf(&data[..], &ImportOptions::default()) {
+            Err(TosmError::InvalidPbf(message)) => assert!(message.contains("raw size")),
+            _ => panic!("expected an invalid pbf error"),
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! Spatial queries over the nodes and way geometry of a [`TOSMFile`].
+
+use std::collections::HashSet;
+
+use kdtree::KdTree;
+
+use crate::geo::{
+    distance_m, interpolate, project_onto_segment, segment_intersects_box, unwrap_lons,
+    METRES_PER_DEGREE,
+};
+use crate::{check_coordinate, dist_haversine, BoundingBox, Node, TOSMFile, TosmError, Way};
+
+/// Segments longer than this get evenly spaced points between their nodes in the way point
+/// index, so every point of a way lies within half this distance of an indexed point. The
+/// nearest-way searches then only need that much slack, however long the longest segment,
+/// such as a ferry line, is.
+pub(crate) const WAY_POINT_SPACING_M: f64 = 50.0;
+
+/// The closest point on a way to a query coordinate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WayMatch {
+    pub way_id: u64,
+    /// Index of the segment between `node_ids[segment_index]` and `node_ids[segment_index + 1]`.
+    pub segment_index: usize,
+    /// Position of the projected point along the segment, from 0 to 1.
+    pub fraction: f64,
+    pub lat: f64,
+    pub lon: f64,
+    pub distance_m: f64,
+}
+
+/// A node returned by a proximity query, with its distance from the query point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeDistance {
+    pub node_id: u64,
+    pub distance_m: f64,
+}
+
+/// Result of [`TOSMFile::reverse_geocode`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedWay<'a> {
+    pub name: &'a str,
+    pub way: &'a Way,
+    pub distance_m: f64,
+}
+
+impl TOSMFile {
+    /// Snaps a coordinate onto the closest way segment, or returns `None` if the file has no ways.
+    pub fn nearest_way(&self, lat: f64, lon: f64) -> Result<Option<WayMatch>, TosmError> {
+        self.nearest_way_where(lat, lon, f64::INFINITY, |_| true)
+    }
+
+    /// Returns every node within `radius_m` metres, closest first. When `filter` is given only
+    /// nodes it accepts are returned, e.g. `Some(&|n| file.is_way_node(n.id()))`.
+    pub fn nodes_within(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        filter: Option<&dyn Fn(&Node) -> bool>,
+    ) -> Result<Vec<NodeDistance>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let found = self
+            .kd_tree
+            .within(&[lat, lon], radius_m / 1000.0, &dist_haversine)
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        let mut nodes: Vec<NodeDistance> = found
+            .into_iter()
+            .map(|(dist_km, &node_id)| NodeDistance {
+                node_id,
+                distance_m: dist_km * 1000.0,
+            })
+            .filter(|n| self.accepts(n.node_id, filter))
+            .collect();
+        nodes.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+
+        Ok(nodes)
+    }
+
+    /// Returns the `k` closest nodes accepted by `filter`, closest first.
+    pub fn k_nearest_nodes(
+        &self,
+        lat: f64,
+        lon: f64,
+        k: usize,
+        filter: Option<&dyn Fn(&Node) -> bool>,
+    ) -> Result<Vec<NodeDistance>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let query = [lat, lon];
+        let nearest = self
+            .kd_tree
+            .iter_nearest(&query, &dist_haversine)
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        Ok(nearest
+            .filter(|(_, &node_id)| self.accepts(node_id, filter))
+            .take(k)
+            .map(|(dist_km, &node_id)| NodeDistance {
+                node_id,
+                distance_m: dist_km * 1000.0,
+            })
+            .collect())
+    }
+
+    /// Whether the node is referenced by at least one way.
+    pub fn is_way_node(&self, id: u64) -> bool {
+        self.node_ways.contains_key(&id)
+    }
+
+    fn accepts(&self, node_id: u64, filter: Option<&dyn Fn(&Node) -> bool>) -> bool {
+        match (filter, self.node(node_id)) {
+            (None, _) => true,
+            (Some(filter), Some(node)) => filter(node),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Returns the ids of all nodes inside the box. A box with `min_lon > max_lon` crosses the
+    /// antimeridian.
+    pub fn nodes_in_bbox(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Result<Vec<u64>, TosmError> {
+        let mut ids = vec![];
+        for bbox in split_bbox(min_lat, min_lon, max_lat, max_lon)? {
+            ids.extend(in_box(&self.kd_tree, &bbox)?);
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns the ids of all ways whose geometry intersects the box, including ways that only
+    /// pass through it without having a node inside. A box with `min_lon > max_lon` crosses the
+    /// antimeridian.
+    pub fn ways_in_bbox(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Result<Vec<u64>, TosmError> {
+        let boxes = split_bbox(min_lat, min_lon, max_lat, max_lon)?;
+        // Unwrapped way geometry may extend past ±180, so it is compared against the query
+        // boxes shifted by a full turn in either direction as well.
+        let shifted: Vec<BoundingBox> = boxes
+            .iter()
+            .flat_map(|b| {
+                [-360.0, 0.0, 360.0].map(|shift| BoundingBox {
+                    min_lon: b.min_lon + shift,
+                    max_lon: b.max_lon + shift,
+                    ..*b
+                })
+            })
+            .collect();
+
+        let mut candidates = vec![];
+        for padded in boxes.iter().flat_map(pad) {
+            candidates.extend(in_box(self.way_point_index(), &padded)?);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut ids = vec![];
+        for way in candidates.into_iter().map(|i| &self.ways[i as usize]) {
+            let coords = unwrap_lons(&self.way_coords(way));
+            let segments: Vec<(&[f64; 2], &[f64; 2])> = match coords.len() {
+                1 => vec![(&coords[0], &coords[0])],
+                _ => coords.windows(2).map(|w| (&w[0], &w[1])).collect(),
+            };
+            let hit = segments.iter().any(|(a, b)| {
+                shifted
+                    .iter()
+                    .any(|bbox| segment_intersects_box(**a, **b, bbox))
+            });
+            if hit {
+                ids.push(way.id);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns the closest way that has a name, such as the street under a map pin, if one lies
+    /// within `max_distance_m`.
+    pub fn reverse_geocode(
+        &self,
+        lat: f64,
+        lon: f64,
+        max_distance_m: f64,
+    ) -> Result<Option<NamedWay<'_>>, TosmError> {
+        let found = self.nearest_way_where(lat, lon, max_distance_m, |way| way.name.is_some())?;
+
+        Ok(found.and_then(|m| {
+            let way = self.way(m.way_id)?;
+            Some(NamedWay {
+                name: way.name()?,
+                way,
+                distance_m: m.distance_m,
+            })
+        }))
+    }
+
+    /// Finds the closest segment of a way accepted by `predicate`, no further than
+    /// `max_distance_m` away.
+    ///
+    /// Way points are visited in order of distance. Every point of a way lies within half of
+    /// [`WAY_POINT_SPACING_M`] of one of its way points, so once the next way point is further
+    /// than the best match plus that slack, no unvisited way can be closer.
+    pub(crate) fn nearest_way_where<F>(
+        &self,
+        lat: f64,
+        lon: f64,
+        max_distance_m: f64,
+        predicate: F,
+    ) -> Result<Option<WayMatch>, TosmError>
+    where
+        F: Fn(&Way) -> bool,
+    {
+        check_coordinate(None, lat, lon)?;
+        let query = [lat, lon];
+        let nearest = self
+            .way_point_index()
+            .iter_nearest(&query, &dist_haversine)
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        let slack = WAY_POINT_SPACING_M / 2.0;
+        let mut best: Option<WayMatch> = None;
+        let mut visited = HashSet::new();
+
+        for (dist_km, &way_index) in nearest {
+            let bound = best
+                .as_ref()
+                .map_or(max_distance_m, |b| b.distance_m.min(max_distance_m));
+            if dist_km * 1000.0 - slack > bound {
+                break;
+            }
+            if !visited.insert(way_index) {
+                continue;
+            }
+            let way = &self.ways[way_index as usize];
+            if !predicate(way) {
+                continue;
+            }
+
+            if let Some(candidate) = self.closest_on_way(way, query) {
+                let closer = best
+                    .as_ref()
+                    .is_none_or(|b| candidate.distance_m < b.distance_m);
+                if closer && candidate.distance_m <= max_distance_m {
+                    best = Some(candidate);
+                }
+            }
+        }
+
+        Ok(best)
+    }
+
+    /// The closest point of every way accepted by `predicate` within `radius_m`, closest first.
+    pub(crate) fn ways_within_where<F>(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        predicate: F,
+    ) -> Result<Vec<WayMatch>, TosmError>
+    where
+        F: Fn(&Way) -> bool,
+    {
+        check_coordinate(None, lat, lon)?;
+        let query = [lat, lon];
+        let found = self
+            .way_point_index()
+            .within(
+                &query,
+                (radius_m + WAY_POINT_SPACING_M / 2.0) / 1000.0,
+                &dist_haversine,
+            )
+            .map_err(|_| TosmError::InvalidCoordinate {
+                node_id: None,
+                lat,
+                lon,
+            })?;
+
+        let mut visited = HashSet::new();
+        let mut found: Vec<WayMatch> = found
+            .into_iter()
+            .filter(|&(_, &way_index)| visited.insert(way_index))
+            .map(|(_, &way_index)| &self.ways[way_index as usize])
+            .filter(|way| predicate(way))
+            .filter_map(|way| self.closest_on_way(way, query))
+            .filter(|m| m.distance_m <= radius_m)
+            .collect();
+        found.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+
+        Ok(found)
+    }
+
+    fn closest_on_way(&self, way: &Way, query: [f64; 2]) -> Option<WayMatch> {
+        let coords = self.way_coords(way);
+
+        let segments: Vec<(usize, [f64; 2], [f64; 2])> = if coords.len() == 1 {
+            vec![(0, coords[0], coords[0])]
+        } else {
+            coords
+                .windows(2)
+                .enumerate()
+                .map(|(i, w)| (i, w[0], w[1]))
+                .collect()
+        };
+
+        let mut best: Option<WayMatch> = None;
+        for (segment_index, a, b) in segments {
+            let (fraction, p) = project_onto_segment(query, a, b);
+            let distance_m = distance_m(query, p);
+            if best.as_ref().is_none_or(|m| distance_m < m.distance_m) {
+                best = Some(WayMatch {
+                    way_id: way.id,
+                    segment_index,
+                    fraction,
+                    lat: p[0],
+                    lon: p[1],
+                    distance_m,
+                });
+            }
+        }
+
+        best
+    }
+}
+
+/// The points of a way's polyline indexed for the nearest-way searches: its nodes and, on
+/// segments longer than [`WAY_POINT_SPACING_M`], evenly spaced points in between.
+pub(crate) fn way_points(coords: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let mut points: Vec<[f64; 2]> = coords.first().copied().into_iter().collect();
+    for pair in coords.windows(2) {
+        let pieces = (distance_m(pair[0], pair[1]) / WAY_POINT_SPACING_M)
+            .ceil()
+            .max(1.0);
+        for k in 1..=pieces as usize {
+            points.push(interpolate(pair[0], pair[1], k as f64 / pieces));
+        }
+    }
+    points
+}
+
+fn split_bbox(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+) -> Result<Vec<BoundingBox>, TosmError> {
+    check_coordinate(None, min_lat, min_lon)?;
+    check_coordinate(None, max_lat, max_lon)?;
+    if min_lat > max_lat {
+        return Err(TosmError::InvalidCoordinate {
+            node_id: None,
+            lat: min_lat,
+            lon: min_lon,
+        });
+    }
+
+    let bbox = |min_lon, max_lon| BoundingBox {
+        min_lat,
+        min_lon,
+        max_lat,
+        max_lon,
+    };
+    if min_lon <= max_lon {
+        Ok(vec![bbox(min_lon, max_lon)])
+    } else {
+        Ok(vec![bbox(min_lon, 180.0), bbox(-180.0, max_lon)])
+    }
+}
+
+/// The values of the points of `tree` inside `bbox`, which must not cross the antimeridian.
+fn in_box<'a, T: PartialEq + Copy>(
+    tree: &'a KdTree<f64, T, [f64; 2]>,
+    bbox: &BoundingBox,
+) -> Result<impl Iterator<Item = T> + 'a, TosmError> {
+    let inside = |_: &[f64], p: &[f64]| {
+        let contained = (bbox.min_lat..=bbox.max_lat).contains(&p[0])
+            && (bbox.min_lon..=bbox.max_lon).contains(&p[1]);
+        if contained {
+            0.0
+        } else {
+            1.0
+        }
+    };
+    // The kd-tree prunes a subtree when the query point clamped into the subtree's bounds is
+    // "further" than the radius. With the query point at the centre of the box, that clamped
+    // point lies inside the box exactly when the subtree's bounds overlap it, so a zero radius
+    // with this distance function is an exact box query.
+    let centre = [
+        (bbox.min_lat + bbox.max_lat) / 2.0,
+        (bbox.min_lon + bbox.max_lon) / 2.0,
+    ];
+    let found = tree
+        .within(&centre, 0.0, &inside)
+        .map_err(|_| TosmError::InvalidCoordinate {
+            node_id: None,
+            lat: centre[0],
+            lon: centre[1],
+        })?;
+    Ok(found.into_iter().map(|(_, &value)| value))
+}
+
+/// `bbox` grown by [`WAY_POINT_SPACING_M`] on every side and split at the antimeridian. Every
+/// point of a way lies within half that spacing of one of its way points, so a way passing
+/// through `bbox` has a way point in the grown box.
+fn pad(bbox: &BoundingBox) -> Vec<BoundingBox> {
+    let margin = WAY_POINT_SPACING_M / METRES_PER_DEGREE;
+    let min_lat = (bbox.min_lat - margin).max(-90.0);
+    let max_lat = (bbox.max_lat + margin).min(90.0);
+    let lon_margin = margin / min_lat.abs().max(max_lat.abs()).to_radians().cos();
+    let (min_lon, max_lon) = (bbox.min_lon - lon_margin, bbox.max_lon + lon_margin);
+
+    let part = |min_lon: f64, max_lon: f64| BoundingBox {
+        min_lat,
+        min_lon,
+        max_lat,
+        max_lon,
+    };
+    // Near the poles the margin covers every longitude.
+    if max_lon - min_lon >= 360.0 {
+        return vec![part(-180.0, 180.0)];
+    }
+    let mut parts = vec![part(min_lon.max(-180.0), max_lon.min(180.0))];
+    if min_lon < -180.0 {
+        parts.push(part(min_lon + 360.0, 180.0));
+    }
+    if max_lon > 180.0 {
+        parts.push(part(-180.0, max_lon - 360.0));
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Node, NodeDistance, TOSMFile};
+
+    #[test]
+    fn snaps_to_long_segments() {
+        // Way 10 is a long straight road whose vertices are both far from the query point,
+        // while node 3 of way 11 is the closest vertex.
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.0, "lon": -21.1},
+                {"id": 2, "lat": 64.0, "lon": -20.9},
+                {"id": 3, "lat": 64.003, "lon": -21.0},
+                {"id": 4, "lat": 64.004, "lon": -21.0}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null},
+                {"id": 11, "node_ids": [3, 4], "one_way": false, "name": null}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+        assert_eq!(file.nearest_node(64.001, -21.0).unwrap(), Some(3));
+
+        let m = file.nearest_way(64.001, -21.0).unwrap().unwrap();
+        assert_eq!(m.way_id, 10);
+        assert_eq!(m.segment_index, 0);
+        assert!((m.fraction - 0.5).abs() < 1e-6);
+        assert!((m.lat - 64.0).abs() < 1e-9);
+        assert!((m.distance_m - 111.2).abs() < 1.0);
+
+        // The long segment is found by its middle, kilometres from both of its nodes.
+        let found = file
+            .ways_within_where(64.001, -21.0, 250.0, |_| true)
+            .unwrap();
+        let ids: Vec<u64> = found.iter().map(|m| m.way_id).collect();
+        assert_eq!(ids, [10, 11]);
+        assert!(file
+            .ways_within_where(64.001, -21.0, 100.0, |way| way.id() == 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn reverse_geocodes_to_named_ways() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9390},
+                {"id": 2, "lat": 64.1420, "lon": -21.9380},
+                {"id": 3, "lat": 64.1424, "lon": -21.9390},
+                {"id": 4, "lat": 64.1424, "lon": -21.9380}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null},
+                {"id": 11, "node_ids": [3, 4], "one_way": false, "name": "Fjólugata"}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let found = file
+            .reverse_geocode(64.1421, -21.9385, 100.0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.name, "Fjólugata");
+        assert_eq!(found.way.id(), 11);
+        assert!((found.distance_m - 33.4).abs() < 1.0);
+
+        assert!(file
+            .reverse_geocode(64.1421, -21.9385, 20.0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn finds_nodes_by_distance() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.0, "lon": -21.0},
+                {"id": 2, "lat": 64.001, "lon": -21.0},
+                {"id": 3, "lat": 64.002, "lon": -21.0},
+                {"id": 4, "lat": 64.0005, "lon": -21.0}
+            ],
+            "ways": [{"id": 10, "node_ids": [2, 3], "one_way": false, "name": null}]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let ids = |nodes: Vec<NodeDistance>| -> Vec<u64> {
+            nodes.into_iter().map(|n| n.node_id).collect()
+        };
+
+        let within = file.nodes_within(64.0, -21.0, 150.0, None).unwrap();
+        assert!((within[2].distance_m - 111.2).abs() < 0.5);
+        assert_eq!(ids(within), vec![1, 4, 2]);
+
+        let on_way = |n: &Node| file.is_way_node(n.id());
+        assert_eq!(
+            ids(file
+                .nodes_within(64.0, -21.0, 150.0, Some(&on_way))
+                .unwrap()),
+            vec![2]
+        );
+        assert_eq!(
+            ids(file.k_nearest_nodes(64.0, -21.0, 2, None).unwrap()),
+            vec![1, 4]
+        );
+        assert_eq!(
+            ids(file.k_nearest_nodes(64.0, -21.0, 5, Some(&on_way)).unwrap()),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn queries_bounding_boxes() {
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.0, "lon": -21.1},
+                {"id": 2, "lat": 64.0, "lon": -20.9},
+                {"id": 3, "lat": 64.05, "lon": -21.0},
+                {"id": 4, "lat": 65.0, "lon": 179.9},
+                {"id": 5, "lat": 65.0, "lon": -179.9}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": null},
+                {"id": 11, "node_ids": [4, 5], "one_way": false, "name": null}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let mut nodes = file.nodes_in_bbox(63.9, -21.05, 64.1, -20.95).unwrap();
+        nodes.sort();
+        assert_eq!(nodes, vec![3]);
+        assert_eq!(
+            file.ways_in_bbox(63.9, -21.05, 64.1, -20.95).unwrap(),
+            vec![10]
+        );
+        assert!(file
+            .ways_in_bbox(64.01, -21.05, 64.1, -20.95)
+            .unwrap()
+            .is_empty());
+
+        let mut nodes = file.nodes_in_bbox(64.9, 179.0, 65.1, -179.95).unwrap();
+        nodes.sort();
+        assert_eq!(nodes, vec![4]);
+        assert_eq!(
+            file.ways_in_bbox(64.9, 179.95, 65.1, -179.95).unwrap(),
+            vec![11]
+        );
+        assert!(file.ways_in_bbox(64.9, 0.0, 65.1, 1.0).unwrap().is_empty());
+
+        // Boxes smaller than the way point spacing, crossed by a way between its way points.
+        assert_eq!(
+            file.ways_in_bbox(63.99999, -21.01201, 64.00001, -21.01199)
+                .unwrap(),
+            vec![10]
+        );
+        assert_eq!(
+            file.ways_in_bbox(64.99999, 179.99999, 65.00001, -179.99999)
+                .unwrap(),
+            vec![11]
+        );
+        assert!(file
+            .ways_in_bbox(64.00002, -21.01201, 64.00004, -21.01199)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,625 @@
+//! Contraction hierarchies, an offline preprocessing of the road graph that lets shortest-path
+//! queries settle only a few hundred vertices.
+//!
+//! Vertices are contracted one at a time, least important first. Contracting a vertex removes
+//! it from the remaining graph and adds a shortcut `u -> w` for every path `u -> v -> w` that
+//! may be the only shortest path between its ends. A query runs Dijkstra from both ends, each
+//! side only following arcs towards vertices contracted later, and unpacks the shortcuts on the
+//! path through the best meeting vertex.
+
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Graph, HeapEntry, Leg, Path, Profile};
+use crate::TOSMFile;
+
+/// Vertices a witness search settles before giving up. Giving up early only adds shortcuts that
+/// were not strictly needed.
+const WITNESS_SETTLE_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum ArcKind {
+    /// An edge of the road graph, by index.
+    Edge(u32),
+    /// A shortcut over two other arcs, by index.
+    Shortcut(u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct ChArc {
+    source: u32,
+    target: u32,
+    weight: f64,
+    distance_m: f64,
+    kind: ArcKind,
+}
+
+/// The arcs of a contracted graph, stored in compressed sparse row form.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ContractionHierarchy {
+    profile: Profile,
+    /// [`Graph::fingerprint`] of the graph the hierarchy was built from, to detect a hierarchy
+    /// that doesn't fit, e.g. after an edit changed a speed limit.
+    graph_fingerprint: u64,
+    /// Whether the file's current graph has `graph_fingerprint`, checked by the first router so
+    /// later routers skip hashing the graph. Reset by [`TOSMFile::build_indexes`].
+    #[serde(skip)]
+    fits_file: OnceLock<bool>,
+    arcs: Vec<ChArc>,
+    /// Arcs leaving each vertex towards a vertex contracted later, for the forward search.
+    up_first: Vec<u32>,
+    up: Vec<u32>,
+    /// Arcs entering each vertex from a vertex contracted later, for the backward search.
+    down_first: Vec<u32>,
+    down: Vec<u32>,
+}
+
+impl TOSMFile {
+    /// Preprocesses the road graph of a profile into a contraction hierarchy, replacing any
+    /// earlier one for the profile. It is saved along with the file, and routers for the profile
+    /// built from the file use it to answer queries much faster. Building it takes a while on
+    /// large extracts, so do it once before writing the `.tosm` file.
+    pub fn build_contraction_hierarchy(&mut self, profile: Profile) {
+        let graph = Graph::new(self, profile);
+        let ch = ContractionHierarchy::build(&graph);
+        self.contraction_hierarchies
+            .retain(|existing| existing.profile != profile);
+        self.contraction_hierarchies.push(ch);
+    }
+
+    /// Whether the file carries a contraction hierarchy for the profile.
+    pub fn has_contraction_hierarchy(&self, profile: Profile) -> bool {
+        self.contraction_hierarchies
+            .iter()
+            .any(|ch| ch.profile == profile)
+    }
+}
+
+impl ContractionHierarchy {
+    pub fn build(graph: &Graph) -> Self {
+        let n = graph.len();
+        let mut contractor = Contractor {
+            arcs: vec![],
+            outgoing: vec![HashMap::new(); n],
+            incoming: vec![HashMap::new(); n],
+            contracted: vec![false; n],
+            contracted_neighbours: vec![0; n],
+        };
+        for v in 0..n as u32 {
+            for (edge_index, edge) in graph.edges_from(v) {
+                contractor.insert(ChArc {
+                    source: v,
+                    target: edge.target,
+                    weight: edge.weight,
+                    distance_m: edge.distance_m,
+                    kind: ArcKind::Edge(edge_index),
+                });
+            }
+        }
+
+        let mut queue: BinaryHeap<HeapEntry> = (0..n as u32)
+            .map(|vertex| HeapEntry {
+                cost: contractor.priority(vertex),
+                vertex,
+            })
+            .collect();
+
+        let mut up: Vec<Vec<u32>> = vec![vec![]; n];
+        let mut down: Vec<Vec<u32>> = vec![vec![]; n];
+        while let Some(HeapEntry { vertex, .. }) = queue.pop() {
+            if contractor.contracted[vertex as usize] {
+                continue;
+            }
+
+            // Priorities go stale as neighbours are contracted. Recompute lazily and put the
+            // vertex back if it is no longer the least important.
+            let priority = contractor.priority(vertex);
+            if queue.peek().is_some_and(|next| priority > next.cost) {
+                queue.push(HeapEntry {
+                    cost: priority,
+                    vertex,
+                });
+                continue;
+            }
+
+            let (outgoing, incoming) = contractor.contract(vertex);
+            up[vertex as usize] = outgoing;
+            down[vertex as usize] = incoming;
+        }
+
+        let (up_first, up) = flatten(up);
+        let (down_first, down) = flatten(down);
+        ContractionHierarchy {
+            profile: graph.profile,
+            graph_fingerprint: graph.fingerprint(),
+            fits_file: OnceLock::from(true),
+            arcs: contractor.arcs,
+            up_first,
+            up,
+            down_first,
+            down,
+        }
+    }
+
+    /// Whether the hierarchy was built from this graph, which must be the graph of the file
+    /// holding the hierarchy.
+    pub fn fits(&self, graph: &Graph) -> bool {
+        self.profile == graph.profile
+            && *self
+                .fits_file
+                .get_or_init(|| self.graph_fingerprint == graph.fingerprint())
+    }
+
+    /// Forgets whether the hierarchy fits the file, after the file's data changed.
+    pub fn recheck(&mut self) {
+        self.fits_file = OnceLock::new();
+    }
+
+    /// Bidirectional search from the cheapest of `sources` to the cheapest of `targets`, with
+    /// the same semantics as the plain search of the router.
+    pub fn query(&self, graph: &Graph, sources: &[Leg], targets: &[Leg]) -> Option<Path> {
+        let mut forward = Side::new(sources);
+        let mut backward = Side::new(targets);
+        let mut best: Option<(f64, u32)> = None;
+
+        loop {
+            let bound = best.map_or(f64::INFINITY, |(total, _)| total);
+            let f = forward.heap.peek().map(|e| e.cost).filter(|&c| c < bound);
+            let b = backward.heap.peek().map(|e| e.cost).filter(|&c| c < bound);
+            let (side, other, arcs, first, is_forward) = match (f, b) {
+                (None, None) => break,
+                (Some(f), Some(b)) if b < f => {
+                    (&mut backward, &forward, &self.down, &self.down_first, false)
+                }
+                (None, Some(_)) => (&mut backward, &forward, &self.down, &self.down_first, false),
+                _ => (&mut forward, &backward, &self.up, &self.up_first, true),
+            };
+
+            let HeapEntry { cost, vertex } = side.heap.pop()?;
+            if cost > side.dist[&vertex] {
+                continue;
+            }
+            if let Some(&d) = other.dist.get(&vertex) {
+                if best.is_none_or(|(total, _)| cost + d < total) {
+                    best = Some((cost + d, vertex));
+                }
+            }
+
+            let range = first[vertex as usize] as usize..first[vertex as usize + 1] as usize;
+            for &arc_index in &arcs[range] {
+                let arc = &self.arcs[arc_index as usize];
+                let next_vertex = if is_forward { arc.target } else { arc.source };
+                let next = cost + arc.weight;
+                if side.dist.get(&next_vertex).is_none_or(|&d| next < d) {
+                    side.dist.insert(next_vertex, next);
+                    side.parent.insert(next_vertex, arc_index);
+                    side.heap.push(HeapEntry {
+                        cost: next,
+                        vertex: next_vertex,
+                    });
+                }
+            }
+        }
+
+        let (_, meeting) = best?;
+
+        let mut arcs = vec![];
+        let mut start = meeting;
+        while let Some(&arc_index) = forward.parent.get(&start) {
+            arcs.push(arc_index);
+            start = self.arcs[arc_index as usize].source;
+        }
+        arcs.reverse();
+        let mut end = meeting;
+        while let Some(&arc_index) = backward.parent.get(&end) {
+            arcs.push(arc_index);
+            end = self.arcs[arc_index as usize].target;
+        }
+
+        let edges = self.unpack(&arcs);
+        let mut vertices = vec![start];
+        vertices.extend(edges.iter().map(|&e| graph.edges[e as usize].target));
+
+        Some(Path {
+            vertices,
+            edges,
+            entry: sources.iter().position(|leg| leg.vertex == start)?,
+            exit: targets.iter().position(|leg| leg.vertex == end)?,
+        })
+    }
+
+    /// Exhaustive Dijkstra from `legs` over the arcs towards vertices contracted later, forward
+    /// or against the arc direction. Returns every settled vertex with its cost and distance.
+    pub fn upward(&self, legs: &[Leg], forward: bool) -> Vec<(u32, f64, f64)> {
+        let (arcs, first) = if forward {
+            (&self.up, &self.up_first)
+        } else {
+            (&self.down, &self.down_first)
+        };
+        let mut side = Side::new(legs);
+        let mut distances: HashMap<u32, f64> = HashMap::new();
+        for leg in legs {
+            if side.dist[&leg.vertex] == leg.weight {
+                distances.entry(leg.vertex).or_insert(leg.distance_m);
+            }
+        }
+
+        let mut settled = vec![];
+        while let Some(HeapEntry { cost, vertex }) = side.heap.pop() {
+            if cost > side.dist[&vertex] {
+                continue;
+            }
+            let distance_m = distances[&vertex];
+            settled.push((vertex, cost, distance_m));
+
+            let range = first[vertex as usize] as usize..first[vertex as usize + 1] as usize;
+            for &arc_index in &arcs[range] {
+                let arc = &self.arcs[arc_index as usize];
+                let next_vertex = if forward { arc.target } else { arc.source };
+                let next = cost + arc.weight;
+                if side.dist.get(&next_vertex).is_none_or(|&d| next < d) {
+                    side.dist.insert(next_vertex, next);
+                    distances.insert(next_vertex, distance_m + arc.distance_m);
+                    side.heap.push(HeapEntry {
+                        cost: next,
+                        vertex: next_vertex,
+                    });
+                }
+            }
+        }
+        settled
+    }
+
+    /// Expands arcs into the road graph edges they stand for.
+    fn unpack(&self, arcs: &[u32]) -> Vec<u32> {
+        let mut edges = vec![];
+        let mut stack: Vec<u32> = arcs.iter().rev().copied().collect();
+        while let Some(arc_index) = stack.pop() {
+            match self.arcs[arc_index as usize].kind {
+                ArcKind::Edge(edge_index) => edges.push(edge_index),
+                ArcKind::Shortcut(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+            }
+        }
+        edges
+    }
+}
+
+/// State of one direction of a bidirectional query. Searches are small, so distances live in
+/// maps rather than vectors sized to the graph.
+struct Side {
+    dist: HashMap<u32, f64>,
+    parent: HashMap<u32, u32>,
+    heap: BinaryHeap<HeapEntry>,
+}
+
+impl Side {
+    fn new(legs: &[Leg]) -> Self {
+        let mut side = Side {
+            dist: HashMap::new(),
+            parent: HashMap::new(),
+            heap: BinaryHeap::new(),
+        };
+        for leg in legs {
+            if side.dist.get(&leg.vertex).is_none_or(|&d| leg.weight < d) {
+                side.dist.insert(leg.vertex, leg.weight);
+                side.heap.push(HeapEntry {
+                    cost: leg.weight,
+                    vertex: leg.vertex,
+                });
+            }
+        }
+        side
+    }
+}
+
+/// The graph while it is being contracted. Between any two remaining vertices only the
+/// cheapest arc is kept.
+struct Contractor {
+    arcs: Vec<ChArc>,
+    /// Arc indexes by neighbour, for the vertices not contracted yet.
+    outgoing: Vec<HashMap<u32, u32>>,
+    incoming: Vec<HashMap<u32, u32>>,
+    contracted: Vec<bool>,
+    contracted_neighbours: Vec<u32>,
+}
+
+impl Contractor {
+    fn insert(&mut self, arc: ChArc) {
+        if arc.source == arc.target {
+            return;
+        }
+        if let Some(&existing) = self.outgoing[arc.source as usize].get(&arc.target) {
+            if self.arcs[existing as usize].weight <= arc.weight {
+                return;
+            }
+        }
+
+        let index = self.arcs.len() as u32;
+        self.arcs.push(arc);
+        self.outgoing[arc.source as usize].insert(arc.target, index);
+        self.incoming[arc.target as usize].insert(arc.source, index);
+    }
+
+    /// Shortcuts needed to contract `v`, found by searching for a witness path around it.
+    fn shortcuts(&self, v: u32) -> Vec<ChArc> {
+        let max_out = self.outgoing[v as usize]
+            .values()
+            .map(|&a| self.arcs[a as usize].weight)
+            .fold(0.0, f64::max);
+
+        let mut shortcuts = vec![];
+        for (&u, &in_arc) in &self.incoming[v as usize] {
+            let in_weight = self.arcs[in_arc as usize].weight;
+            let witness = self.witness_search(u, v, in_weight + max_out);
+
+            for (&w, &out_arc) in &self.outgoing[v as usize] {
+                if w == u {
+                    continue;
+                }
+                let weight = in_weight + self.arcs[out_arc as usize].weight;
+                if witness.get(&w).is_none_or(|&d| d > weight) {
+                    shortcuts.push(ChArc {
+                        source: u,
+                        target: w,
+                        weight,
+                        distance_m: self.arcs[in_arc as usize].distance_m
+                            + self.arcs[out_arc as usize].distance_m,
+                        kind: ArcKind::Shortcut(in_arc, out_arc),
+                    });
+                }
+            }
+        }
+        shortcuts
+    }
+
+    /// Dijkstra from `u` over the remaining graph without `v`, up to `limit`.
+    fn witness_search(&self, u: u32, v: u32, limit: f64) -> HashMap<u32, f64> {
+        let mut dist = HashMap::from([(u, 0.0)]);
+        let mut heap = BinaryHeap::from([HeapEntry {
+            cost: 0.0,
+            vertex: u,
+        }]);
+
+        let mut settled = 0;
+        while let Some(HeapEntry { cost, vertex }) = heap.pop() {
+            if cost > dist[&vertex] {
+                continue;
+            }
+            settled += 1;
+            if cost > limit || settled > WITNESS_SETTLE_LIMIT {
+                break;
+            }
+
+            for (&next_vertex, &arc) in &self.outgoing[vertex as usize] {
+                if next_vertex == v {
+                    continue;
+                }
+                let next = cost + self.arcs[arc as usize].weight;
+                if dist.get(&next_vertex).is_none_or(|&d| next < d) {
+                    dist.insert(next_vertex, next);
+                    heap.push(HeapEntry {
+                        cost: next,
+                        vertex: next_vertex,
+                    });
+                }
+            }
+        }
+        dist
+    }
+
+    /// Edge difference plus the number of contracted neighbours, which spreads contraction
+    /// evenly over the graph.
+    fn priority(&self, v: u32) -> f64 {
+        let removed = self.outgoing[v as usize].len() + self.incoming[v as usize].len();
+        self.shortcuts(v).len() as f64 - removed as f64
+            + self.contracted_neighbours[v as usize] as f64
+    }
+
+    /// Removes `v` from the remaining graph, returning its outgoing and incoming arcs.
+    fn contract(&mut self, v: u32) -> (Vec<u32>, Vec<u32>) {
+        let shortcuts = self.shortcuts(v);
+        self.contracted[v as usize] = true;
+
+        let outgoing = std::mem::take(&mut self.outgoing[v as usize]);
+        let incoming = std::mem::take(&mut self.incoming[v as usize]);
+        for &w in outgoing.keys() {
+            self.incoming[w as usize].remove(&v);
+            self.contracted_neighbours[w as usize] += 1;
+        }
+        for &u in incoming.keys() {
+            self.outgoing[u as usize].remove(&v);
+            if !outgoing.contains_key(&u) {
+                self.contracted_neighbours[u as usize] += 1;
+            }
+        }
+
+        for shortcut in shortcuts {
+            self.insert(shortcut);
+        }
+
+        (
+            outgoing.into_values().collect(),
+            incoming.into_values().collect(),
+        )
+    }
+}
+
+fn flatten(lists: Vec<Vec<u32>>) -> (Vec<u32>, Vec<u32>) {
+    let mut first = Vec::with_capacity(lists.len() + 1);
+    first.push(0);
+    let mut items = vec![];
+    for list in lists {
+        items.extend(list);
+        first.push(items.len() as u32);
+    }
+    (first, items)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::routing::tests::grid;
+    use crate::{Profile, Router, TOSMFile, WriteOptions};
+
+    /// A street grid of `size` by `size` nodes 100 m apart, with a way per row and per column.
+    /// Every tenth row and column is a primary road, and every third other row is one-way.
+    fn city(size: u64) -> TOSMFile {
+        let way = |id: u64, node_ids: &[String], line: u64, one_way: bool| {
+            let highway = if line.is_multiple_of(10) {
+                "primary"
+            } else {
+                "residential"
+            };
+            let oneway = if one_way && !line.is_multiple_of(10) {
+                "yes"
+            } else {
+                "no"
+            };
+            format!(
+                r#"{{"id": {}, "node_ids": [{}], "one_way": false, "name": null,
+                    "tags": {{"highway": "{}", "oneway": "{}"}}}}"#,
+                id,
+                node_ids.join(", "),
+                highway,
+                oneway
+            )
+        };
+        let nodes: Vec<String> = (0..size * size)
+            .map(|i| {
+                let (row, col) = (i / size, i % size);
+                format!(
+                    r#"{{"id": {}, "lat": {}, "lon": {}}}"#,
+                    i + 1,
+                    64.0 + row as f64 * 0.0009,
+                    -21.0 + col as f64 * 0.002
+                )
+            })
+            .collect();
+        let ways: Vec<String> = (0..size)
+            .flat_map(|line| {
+                let row: Vec<String> = (0..size)
+                    .map(|col| (line * size + col + 1).to_string())
+                    .collect();
+                let column: Vec<String> = (0..size)
+                    .map(|row| (row * size + line + 1).to_string())
+                    .collect();
+                [
+                    way(line + 1, &row, line, line % 3 == 0),
+                    way(size + line + 1, &column, line, false),
+                ]
+            })
+            .collect();
+        TOSMFile::from_json_str(&format!(
+            r#"{{"nodes": [{}], "ways": [{}], "relations": []}}"#,
+            nodes.join(", "),
+            ways.join(", ")
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_plain_search() {
+        let plain_file = grid();
+        let plain = Router::new(&plain_file);
+
+        let mut file = grid();
+        file.build_contraction_hierarchy(Profile::Car);
+        let mut blob = vec![];
+        file.write_tosm_with(&mut blob, &WriteOptions::default())
+            .unwrap();
+        assert!(
+            TOSMFile::read_tosm_header(&blob[..])
+                .unwrap()
+                .contraction_hierarchy
+        );
+
+        let file = TOSMFile::from_tosm_reader(&blob[..]).unwrap();
+        assert!(file.has_contraction_hierarchy(Profile::Car));
+        assert!(!file.has_contraction_hierarchy(Profile::Foot));
+        let router = Router::new(&file);
+
+        for from in [1, 2, 3, 4, 5, 6, 8] {
+            for to in [1, 2, 3, 4, 5, 6, 8] {
+                // Compare lengths, as equally short routes may be picked differently.
+                let expected = plain.route(from, to).unwrap().map(|r| r.distance_m);
+                let found = router.route(from, to).unwrap().map(|r| r.distance_m);
+                assert_eq!(found.is_some(), expected.is_some(), "{from} -> {to}");
+                if let (Some(found), Some(expected)) = (found, expected) {
+                    assert!((found - expected).abs() < 1e-6, "{from} -> {to}");
+                }
+            }
+        }
+
+        let found = router
+            .route_between(64.0, -20.9985, 64.0, -20.9995)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.node_ids, vec![2, 5, 4, 1]);
+
+        assert!(router.hierarchy.is_some());
+        // Moving a node changes edge weights but not the shape of the graph.
+        let mut edited = file;
+        edited.nodes[0].lat += 0.0001;
+        edited.build_indexes();
+        assert!(Router::new(&edited).hierarchy.is_none());
+    }
+
+    /// Compares query times with and without the hierarchy on a grid of 40 000 nodes. Run with
+    /// `cargo test --release -- --ignored --nocapture queries_faster`.
+    #[test]
+    #[ignore]
+    fn queries_faster_than_plain_search() {
+        let size = 200;
+        let mut file = city(size);
+        let plain = Router::new(&file);
+        let pairs: Vec<(u64, u64)> = (0..50)
+            .map(|i| {
+                (
+                    i * 797 % (size * size) + 1,
+                    (i * 7919 + 13) % (size * size) + 1,
+                )
+            })
+            .collect();
+
+        let started = Instant::now();
+        let expected: Vec<_> = pairs
+            .iter()
+            .map(|&(from, to)| plain.route(from, to).unwrap().map(|r| r.distance_m))
+            .collect();
+        let plain_time = started.elapsed();
+
+        let started = Instant::now();
+        file.build_contraction_hierarchy(Profile::Car);
+        let build_time = started.elapsed();
+        let router = Router::new(&file);
+        assert!(router.hierarchy.is_some());
+
+        let started = Instant::now();
+        let found: Vec<_> = pairs
+            .iter()
+            .map(|&(from, to)| router.route(from, to).unwrap().map(|r| r.distance_m))
+            .collect();
+        let ch_time = started.elapsed();
+
+        eprintln!(
+            "{} queries: plain {:?}, hierarchy {:?} (built in {:?})",
+            pairs.len(),
+            plain_time,
+            ch_time,
+            build_time
+        );
+        for (found, expected) in found.iter().zip(&expected) {
+            assert_eq!(found.is_some(), expected.is_some());
+            if let (Some(found), Some(expected)) = (found, expected) {
+                assert!((found - expected).abs() < 1e-6);
+            }
+        }
+        assert!(ch_time < plain_time);
+    }
+}
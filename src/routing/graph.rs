@@ -0,0 +1,205 @@
+//! The directed road graph routing runs on.
+
+use std::collections::HashMap;
+
+use super::{turns, Profile};
+use crate::geo::distance_m;
+use crate::TOSMFile;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Edge {
+    pub target: u32,
+    /// Index of the way in [`TOSMFile::ways`] the edge belongs to.
+    pub way: u32,
+    pub distance_m: f64,
+    /// Travel time in seconds, the cost minimised by the searches.
+    pub weight: f64,
+}
+
+/// Every node referenced by a way the profile may use becomes a vertex and every pair of
+/// consecutive way nodes an edge, so ways are split wherever they share a node with another way. Outgoing edges are
+/// stored in compressed sparse row form: the edges of vertex `v` are
+/// `edges[first_out[v]..first_out[v + 1]]`.
+///
+/// Turn restrictions add further vertices for nodes where a restricted manoeuvre may be under
+/// way, see [`turns`]. `vertices` maps node ids to the vertex without restrictions.
+#[derive(Debug, Clone)]
+pub(crate) struct Graph {
+    pub profile: Profile,
+    /// Fastest speed of any edge, in metres per second.
+    pub max_speed_mps: f64,
+    pub node_ids: Vec<u64>,
+    pub coords: Vec<[f64; 2]>,
+    pub vertices: HashMap<u64, u32>,
+    /// The vertices added for turn restrictions, by the node's unrestricted vertex.
+    pub copies: HashMap<u32, Vec<u32>>,
+    pub first_out: Vec<u32>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// A hash of the vertices, edges and edge weights, stable across builds and platforms, so
+    /// that data derived from the graph can tell whether it still matches.
+    pub fn fingerprint(&self) -> u64 {
+        // 64-bit FNV-1a.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut add = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+        for &node_id in &self.node_ids {
+            add(node_id);
+        }
+        for &first in &self.first_out {
+            add(u64::from(first));
+        }
+        for edge in &self.edges {
+            add(u64::from(edge.target));
+            add(u64::from(edge.way));
+            add(edge.distance_m.to_bits());
+            add(edge.weight.to_bits());
+        }
+        hash
+    }
+
+    pub fn new(file: &TOSMFile, profile: Profile) -> Self {
+        let mut graph = Graph {
+            profile,
+            max_speed_mps: profile.max_speed_kmh(file.ways()) / 3.6,
+            node_ids: vec![],
+            coords: vec![],
+            vertices: HashMap::new(),
+            copies: HashMap::new(),
+            first_out: vec![],
+            edges: vec![],
+        };
+
+        let mut arcs: Vec<(u32, Edge)> = vec![];
+        for (way_index, way) in file.ways().iter().enumerate() {
+            let speed_mps = match profile.speed_kmh(way) {
+                Some(speed) => speed / 3.6,
+                None => continue,
+            };
+            let one_way = profile.one_way(way);
+
+            for pair in way.node_ids().windows(2) {
+                if pair[0] == pair[1] {
+                    continue;
+                }
+                let (a, b) = match (file.node(pair[0]), file.node(pair[1])) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue,
+                };
+                let from = graph.vertex(a.id(), [a.lat(), a.lon()]);
+                let to = graph.vertex(b.id(), [b.lat(), b.lon()]);
+                let length = distance_m([a.lat(), a.lon()], [b.lat(), b.lon()]);
+
+                let edge = |target| Edge {
+                    target,
+                    way: way_index as u32,
+                    distance_m: length,
+                    weight: length / speed_mps,
+                };
+                arcs.push((from, edge(to)));
+                if !one_way {
+                    arcs.push((to, edge(from)));
+                }
+            }
+        }
+
+        graph.set_edges(arcs);
+        turns::restrict(&mut graph, file);
+        graph
+    }
+
+    /// Replaces the edges with `(source, edge)` pairs.
+    pub fn set_edges(&mut self, mut arcs: Vec<(u32, Edge)>) {
+        arcs.sort_by_key(|(source, _)| *source);
+        self.first_out = vec![0; self.node_ids.len() + 1];
+        for (source, _) in &arcs {
+            self.first_out[*source as usize + 1] += 1;
+        }
+        for v in 0..self.node_ids.len() {
+            self.first_out[v + 1] += self.first_out[v];
+        }
+        self.edges = arcs.into_iter().map(|(_, edge)| edge).collect();
+    }
+
+    /// Adds a vertex for the node of vertex `v`.
+    pub fn add_copy(&mut self, v: u32) -> u32 {
+        let copy = self.node_ids.len() as u32;
+        self.node_ids.push(self.node_ids[v as usize]);
+        self.coords.push(self.coords[v as usize]);
+        self.copies.entry(v).or_default().push(copy);
+        copy
+    }
+
+    fn vertex(&mut self, node_id: u64, coord: [f64; 2]) -> u32 {
+        if let Some(&v) = self.vertices.get(&node_id) {
+            return v;
+        }
+
+        let v = self.node_ids.len() as u32;
+        self.node_ids.push(node_id);
+        self.coords.push(coord);
+        self.vertices.insert(node_id, v);
+        v
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn edges_from(&self, v: u32) -> impl Iterator<Item = (u32, &Edge)> {
+        let start = self.first_out[v as usize];
+        let end = self.first_out[v as usize + 1];
+        (start..end).map(move |i| (i, &self.edges[i as usize]))
+    }
+
+    /// The unrestricted vertex `v` followed by the vertices added for turn restrictions at the
+    /// same node.
+    pub fn vertices_of(&self, v: u32) -> impl Iterator<Item = u32> + '_ {
+        std::iter::once(v).chain(self.copies.get(&v).into_iter().flatten().copied())
+    }
+
+    /// The cheapest edge from `from` to any vertex of `to`'s node along the given way.
+    pub fn find_edge(&self, from: u32, to: u32, way: u32) -> Option<&Edge> {
+        let to_node = self.node_ids[to as usize];
+        self.edges_from(from)
+            .map(|(_, edge)| edge)
+            .filter(|edge| self.node_ids[edge.target as usize] == to_node && edge.way == way)
+            .min_by(|a, b| a.weight.total_cmp(&b.weight))
+    }
+
+    pub fn edge_source(&self, edge_index: u32) -> u32 {
+        // `first_out` is sorted, so the source is the last vertex whose range starts at or
+        // before the edge.
+        (self.first_out.partition_point(|&start| start <= edge_index) - 1) as u32
+    }
+
+    /// Labels each vertex with the representative of its weakly connected component.
+    pub fn components(&self) -> Vec<u32> {
+        fn find(parent: &mut [u32], mut v: u32) -> u32 {
+            while parent[v as usize] != v {
+                parent[v as usize] = parent[parent[v as usize] as usize];
+                v = parent[v as usize];
+            }
+            v
+        }
+
+        let mut parent: Vec<u32> = (0..self.len() as u32).collect();
+        for v in 0..self.len() as u32 {
+            for (_, edge) in self.edges_from(v) {
+                let (a, b) = (find(&mut parent, v), find(&mut parent, edge.target));
+                if a != b {
+                    parent[a as usize] = b;
+                }
+            }
+        }
+
+        (0..self.len() as u32)
+            .map(|v| find(&mut parent, v))
+            .collect()
+    }
+}
@@ -0,0 +1,307 @@
+//! Turn-by-turn instructions for computed routes.
+//!
+//! A new step starts wherever the route changes street, turns at a junction, makes a U-turn or
+//! enters a roundabout. Turns are classified by the change in bearing between the segments
+//! before and after the step's location.
+
+use serde::{Deserialize, Serialize};
+
+use super::Router;
+use crate::geo::bearing;
+
+/// Segments shorter than this, e.g. from a snapped end lying on a vertex, have no usable
+/// bearing and are left out.
+const MIN_SEGMENT_M: f64 = 0.01;
+
+/// What to do at the start of an [`Instruction`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Maneuver {
+    Depart,
+    Continue,
+    SlightLeft,
+    Left,
+    SharpLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+    /// Enter a roundabout and leave it at the given exit, counting from 1.
+    Roundabout {
+        exit: u32,
+    },
+    Arrive,
+}
+
+/// One step of a route.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub maneuver: Maneuver,
+    /// Name of the street the step follows, when it has one.
+    pub street: Option<String>,
+    /// `[lat, lon]` of the maneuver.
+    pub location: [f64; 2],
+    /// Distance and travel time from this maneuver to the next.
+    pub distance_m: f64,
+    pub duration_s: f64,
+    /// The instruction in English, e.g. "Turn left onto Fjólugata".
+    pub text: String,
+}
+
+/// The stretch of a route between two consecutive points of its geometry.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Segment {
+    pub way: u32,
+    pub distance_m: f64,
+    pub duration_s: f64,
+}
+
+impl<'a> Router<'a> {
+    /// Instructions for a route along `points`, where `nodes` holds the node id of each point
+    /// that is a node and `segments` the stretches between the points.
+    pub(crate) fn instructions(
+        &self,
+        points: &[[f64; 2]],
+        nodes: &[Option<u64>],
+        segments: &[Segment],
+    ) -> Vec<Instruction> {
+        let (mut p, mut n, mut s) = (vec![], vec![], vec![]);
+        if let (Some(&point), Some(&node)) = (points.first(), nodes.first()) {
+            p.push(point);
+            n.push(node);
+        }
+        for (k, &segment) in segments.iter().enumerate() {
+            if segment.distance_m < MIN_SEGMENT_M {
+                let last = n.len() - 1;
+                n[last] = n[last].or(nodes[k + 1]);
+            } else {
+                p.push(points[k + 1]);
+                n.push(nodes[k + 1]);
+                s.push(segment);
+            }
+        }
+        let (points, nodes, segments) = (p, n, s);
+        let Some(&start) = points.first() else {
+            return vec![];
+        };
+
+        let way = |segment: &Segment| &self.file.ways()[segment.way as usize];
+        let name = |segment: &Segment| way(segment).name().map(str::to_string);
+
+        let mut steps = vec![step(
+            Maneuver::Depart,
+            segments.first().and_then(name),
+            start,
+        )];
+        let mut i = 0;
+        while i < segments.len() {
+            let (before, after) = (i.checked_sub(1).map(|k| &segments[k]), &segments[i]);
+
+            if let Some(before) = before {
+                if way(after).roundabout() && !way(before).roundabout() {
+                    let mut roundabout = step(Maneuver::Roundabout { exit: 0 }, None, points[i]);
+                    let mut exit = 0;
+                    loop {
+                        roundabout.add(&segments[i]);
+                        i += 1;
+                        if i == segments.len() {
+                            break;
+                        }
+                        if !way(&segments[i]).roundabout() {
+                            exit += 1;
+                            roundabout.street = name(&segments[i]);
+                            roundabout.add(&segments[i]);
+                            i += 1;
+                            break;
+                        }
+                        if nodes[i].is_some_and(|node| self.has_exit(node)) {
+                            exit += 1;
+                        }
+                    }
+                    roundabout.maneuver = Maneuver::Roundabout { exit };
+                    steps.push(roundabout);
+                    continue;
+                }
+
+                let turn = turn(points[i - 1], points[i], points[i + 1]);
+                let changes_street = name(before) != name(after)
+                    || (way(before).name().is_none() && before.way != after.way);
+                let at_junction = nodes[i].is_some_and(|node| {
+                    self.file
+                        .node_ways
+                        .get(&node)
+                        .is_some_and(|ways| ways.len() > 1)
+                });
+                let turns = !matches!(
+                    turn,
+                    Maneuver::Continue | Maneuver::SlightLeft | Maneuver::SlightRight
+                );
+                if changes_street || turn == Maneuver::UTurn || (turns && at_junction) {
+                    steps.push(step(turn, name(after), points[i]));
+                }
+            }
+
+            steps.last_mut().expect("starts with depart").add(after);
+            i += 1;
+        }
+
+        let end = *points.last().expect("not empty");
+        steps.push(step(Maneuver::Arrive, segments.last().and_then(name), end));
+
+        let heading = points.get(1).map(|&second| compass(bearing(start, second)));
+        for step in &mut steps {
+            step.text = text(step.maneuver, step.street.as_deref(), heading);
+        }
+        steps
+    }
+
+    /// Whether a roundabout can be left at `node` by the router's profile.
+    fn has_exit(&self, node: u64) -> bool {
+        self.graph.vertices.get(&node).is_some_and(|&v| {
+            self.graph
+                .edges_from(v)
+                .any(|(_, edge)| !self.file.ways()[edge.way as usize].roundabout())
+        })
+    }
+}
+
+impl Instruction {
+    fn add(&mut self, segment: &Segment) {
+        self.distance_m += segment.distance_m;
+        self.duration_s += segment.duration_s;
+    }
+}
+
+fn step(maneuver: Maneuver, street: Option<String>, location: [f64; 2]) -> Instruction {
+    Instruction {
+        maneuver,
+        street,
+        location,
+        distance_m: 0.0,
+        duration_s: 0.0,
+        text: String::new(),
+    }
+}
+
+/// The English text of a maneuver. `heading` is the compass direction the route starts in.
+fn text(maneuver: Maneuver, street: Option<&str>, heading: Option<&str>) -> String {
+    let verb = match maneuver {
+        Maneuver::Depart => match heading {
+            Some(heading) => format!("Head {}", heading),
+            None => "Depart".to_string(),
+        },
+        Maneuver::Continue => "Continue".to_string(),
+        Maneuver::SlightLeft => "Turn slightly left".to_string(),
+        Maneuver::Left => "Turn left".to_string(),
+        Maneuver::SharpLeft => "Turn sharply left".to_string(),
+        Maneuver::SlightRight => "Turn slightly right".to_string(),
+        Maneuver::Right => "Turn right".to_string(),
+        Maneuver::SharpRight => "Turn sharply right".to_string(),
+        Maneuver::UTurn => "Make a U-turn".to_string(),
+        Maneuver::Roundabout { exit } => format!("At the roundabout, take exit {}", exit),
+        Maneuver::Arrive => return "Arrive at the destination".to_string(),
+    };
+    match (street, maneuver) {
+        (Some(street), Maneuver::Depart) => format!("{} on {}", verb, street),
+        (Some(street), _) => format!("{} onto {}", verb, street),
+        (None, _) => verb,
+    }
+}
+
+/// Classifies the turn at `b` when travelling from `a` via `b` to `c`.
+fn turn(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> Maneuver {
+    let change = (bearing(b, c) - bearing(a, b) + 540.0).rem_euclid(360.0) - 180.0;
+    let (slight, normal, sharp) = if change < 0.0 {
+        (Maneuver::SlightLeft, Maneuver::Left, Maneuver::SharpLeft)
+    } else {
+        (Maneuver::SlightRight, Maneuver::Right, Maneuver::SharpRight)
+    };
+    match change.abs() {
+        a if a <= 20.0 => Maneuver::Continue,
+        a if a <= 60.0 => slight,
+        a if a <= 135.0 => normal,
+        a if a <= 170.0 => sharp,
+        _ => Maneuver::UTurn,
+    }
+}
+
+fn compass(bearing: f64) -> &'static str {
+    const NAMES: [&str; 8] = [
+        "north",
+        "northeast",
+        "east",
+        "southeast",
+        "south",
+        "southwest",
+        "west",
+        "northwest",
+    ];
+    NAMES[((bearing / 45.0).round() as usize) % 8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Maneuver;
+    use crate::{Router, TOSMFile};
+
+    #[test]
+    fn describes_turns_and_roundabout_exits() {
+        // A roundabout with four arms, travelled counterclockwise, and a street turning west
+        // off the northern arm.
+        let file = TOSMFile::from_json_str(
+            r#"{
+                "nodes": [
+                    {"id": 1, "lat": 64.0003, "lon": -21.0},
+                    {"id": 2, "lat": 64.0, "lon": -21.0006},
+                    {"id": 3, "lat": 63.9997, "lon": -21.0},
+                    {"id": 4, "lat": 64.0, "lon": -20.9994},
+                    {"id": 5, "lat": 63.998, "lon": -21.0},
+                    {"id": 6, "lat": 64.0, "lon": -21.003},
+                    {"id": 7, "lat": 64.002, "lon": -21.0},
+                    {"id": 8, "lat": 64.0, "lon": -20.997},
+                    {"id": 9, "lat": 64.002, "lon": -21.003}
+                ],
+                "ways": [
+                    {"id": 20, "node_ids": [1, 2, 3, 4, 1], "one_way": true, "name": "Hringur",
+                        "tags": {"highway": "residential", "junction": "roundabout",
+                            "name": "Hringur"}},
+                    {"id": 21, "node_ids": [5, 3], "one_way": false, "name": "Suðurgata"},
+                    {"id": 22, "node_ids": [2, 6], "one_way": false, "name": "Vesturgata"},
+                    {"id": 23, "node_ids": [1, 7], "one_way": false, "name": "Norðurgata"},
+                    {"id": 24, "node_ids": [4, 8], "one_way": false, "name": "Austurgata"},
+                    {"id": 25, "node_ids": [7, 9], "one_way": false, "name": "Fjólugata"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let router = Router::new(&file);
+
+        let route = router.route(5, 9).unwrap().unwrap();
+        let steps = &route.instructions;
+        let maneuvers: Vec<Maneuver> = steps.iter().map(|s| s.maneuver).collect();
+        assert_eq!(
+            maneuvers,
+            [
+                Maneuver::Depart,
+                Maneuver::Roundabout { exit: 2 },
+                Maneuver::Left,
+                Maneuver::Arrive
+            ]
+        );
+        assert_eq!(steps[0].text, "Head north on Suðurgata");
+        assert_eq!(
+            steps[1].text,
+            "At the roundabout, take exit 2 onto Norðurgata"
+        );
+        assert_eq!(steps[2].text, "Turn left onto Fjólugata");
+        assert_eq!(steps[2].location, [64.002, -21.0]);
+
+        assert!((steps[0].distance_m - 189.0).abs() < 1.0);
+        let total: f64 = steps.iter().map(|s| s.distance_m).sum();
+        assert!((total - route.distance_m).abs() < 1e-6);
+        assert_eq!(steps[3].distance_m, 0.0);
+
+        let json = serde_json::to_string(&steps[1]).unwrap();
+        assert!(json.contains(r#""maneuver":{"type":"roundabout","exit":2}"#));
+    }
+}
@@ -0,0 +1,184 @@
+//! Reachability within a travel-time budget.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use serde_json::{json, Value};
+
+use super::{HeapEntry, Router};
+use crate::geo::{interpolate, to_plane};
+use crate::hull::concave_hull;
+use crate::TosmError;
+
+/// A node reached within the budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReachedNode {
+    pub node_id: u64,
+    /// Travel time from the origin in seconds.
+    pub cost_s: f64,
+}
+
+/// A way that can be entered within the budget.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReachedWay {
+    pub way_id: u64,
+    /// Travel time from the origin to the first point of the way reached, in seconds.
+    pub cost_s: f64,
+}
+
+/// Result of [`Router::reachable_within`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Isochrone {
+    /// Reached nodes, cheapest first.
+    pub nodes: Vec<ReachedNode>,
+    /// Reached ways, cheapest first.
+    pub ways: Vec<ReachedWay>,
+    /// `[lat, lon]` of the reached nodes followed by the points along edges where the budget
+    /// runs out.
+    points: Vec<[f64; 2]>,
+}
+
+impl<'a> Router<'a> {
+    /// Finds everything reachable from `origin` within `budget_s` seconds of travel with the
+    /// router's profile.
+    pub fn reachable_within(&self, origin: u64, budget_s: f64) -> Result<Isochrone, TosmError> {
+        let source = self.vertex(origin)?;
+        let budget_s = budget_s.max(0.0);
+
+        let mut dist: HashMap<u32, f64> = HashMap::from([(source, 0.0)]);
+        let mut heap = BinaryHeap::from([HeapEntry {
+            cost: 0.0,
+            vertex: source,
+        }]);
+        let mut isochrone = Isochrone {
+            nodes: vec![],
+            ways: vec![],
+            points: vec![],
+        };
+        let mut frontier = vec![];
+        let mut ways: HashMap<u32, f64> = HashMap::new();
+        // Turn restrictions can give a node several vertices; the first one settled is the
+        // cheapest.
+        let mut reached: HashSet<u64> = HashSet::new();
+
+        while let Some(HeapEntry { cost, vertex }) = heap.pop() {
+            if cost > budget_s {
+                break;
+            }
+            if cost > dist[&vertex] {
+                continue;
+            }
+            let node_id = self.graph.node_ids[vertex as usize];
+            if reached.insert(node_id) {
+                isochrone.nodes.push(ReachedNode {
+                    node_id,
+                    cost_s: cost,
+                });
+                isochrone.points.push(self.graph.coords[vertex as usize]);
+            }
+
+            for (_, edge) in self.graph.edges_from(vertex) {
+                if cost < budget_s {
+                    ways.entry(edge.way).or_insert(cost);
+                }
+
+                let next = cost + edge.weight;
+                if next > budget_s {
+                    let t = (budget_s - cost) / edge.weight;
+                    frontier.push(interpolate(
+                        self.graph.coords[vertex as usize],
+                        self.graph.coords[edge.target as usize],
+                        t,
+                    ));
+                } else if dist.get(&edge.target).is_none_or(|&d| next < d) {
+                    dist.insert(edge.target, next);
+                    heap.push(HeapEntry {
+                        cost: next,
+                        vertex: edge.target,
+                    });
+                }
+            }
+        }
+
+        isochrone.points.extend(frontier);
+        isochrone.ways = ways
+            .into_iter()
+            .map(|(way, cost_s)| ReachedWay {
+                way_id: self.way_id(way),
+                cost_s,
+            })
+            .collect();
+        isochrone
+            .ways
+            .sort_by(|a, b| a.cost_s.total_cmp(&b.cost_s).then(a.way_id.cmp(&b.way_id)));
+
+        Ok(isochrone)
+    }
+}
+
+impl Isochrone {
+    /// Outline of the reached area as a closed `[lat, lon]` ring, or `None` when the reached
+    /// points don't span an area. See [`Isochrone::to_geojson`] for `concavity`.
+    pub fn concave_hull(&self, concavity: f64) -> Option<Vec<[f64; 2]>> {
+        let origin = *self.points.first()?;
+        let plane: Vec<[f64; 2]> = self.points.iter().map(|&p| to_plane(origin, p)).collect();
+
+        let hull = concave_hull(&plane, concavity);
+        if hull.len() < 3 {
+            return None;
+        }
+        let mut ring: Vec<[f64; 2]> = hull.iter().map(|&i| self.points[i]).collect();
+        ring.push(ring[0]);
+        Some(ring)
+    }
+
+    /// The outline of the reached area as a GeoJSON `Feature` with a `Polygon` geometry, or
+    /// `None` when the reached points don't span an area. Lower `concavity` values follow the
+    /// road network more tightly; around 2 works well and `f64::INFINITY` gives the convex hull.
+    pub fn to_geojson(&self, concavity: f64) -> Option<Value> {
+        let ring = self.concave_hull(concavity)?;
+        let coordinates: Vec<[f64; 2]> = ring.iter().map(|p| [p[1], p[0]]).collect();
+
+        Some(json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [coordinates],
+            },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::tests::grid;
+    use crate::{Profile, Router};
+
+    #[test]
+    fn finds_reachable_area() {
+        let file = grid();
+        let router = Router::with_profile(&file, Profile::Foot);
+
+        // About 150 m on foot: the three nodes one block away, but not the far corners.
+        let isochrone = router.reachable_within(2, 110.0).unwrap();
+        let mut ids: Vec<u64> = isochrone.nodes.iter().map(|n| n.node_id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3, 5]);
+        assert_eq!(isochrone.nodes[0].cost_s, 0.0);
+
+        let way_ids: Vec<u64> = isochrone.ways.iter().map(|w| w.way_id).collect();
+        assert_eq!(way_ids.len(), 6);
+        assert!(!way_ids.contains(&16));
+
+        let geojson = isochrone.to_geojson(2.0).unwrap();
+        assert_eq!(geojson["geometry"]["type"], "Polygon");
+        let ring = geojson["geometry"]["coordinates"][0].as_array().unwrap();
+        assert!(ring.len() >= 4);
+        assert_eq!(ring.first(), ring.last());
+
+        let isochrone = router.reachable_within(2, 0.0).unwrap();
+        assert_eq!(isochrone.nodes.len(), 1);
+        assert!(isochrone.ways.is_empty());
+        assert!(isochrone.to_geojson(2.0).is_none());
+    }
+}
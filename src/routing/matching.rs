@@ -0,0 +1,412 @@
+//! Map matching of GPS traces with a hidden Markov model, after Newson and Krumm, "Hidden
+//! Markov Map Matching Through Noise and Sparseness" (2009).
+//!
+//! Every GPS point has candidate positions on nearby ways. A candidate is more likely the closer
+//! it is to its point, and a move between candidates of consecutive points is more likely the
+//! closer its route distance is to the straight-line distance between the points. Viterbi picks
+//! the most likely sequence of candidates, and the forward-backward algorithm gives each chosen
+//! candidate's posterior probability as its confidence.
+
+use std::collections::{BinaryHeap, HashMap};
+
+use super::{HeapEntry, Router, Snap, NO_EDGE};
+use crate::geo::distance_m;
+use crate::TosmError;
+
+/// Tuning of [`Router::match_trace_with`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchOptions {
+    /// How far from a GPS point candidate ways are looked for.
+    pub search_radius_m: f64,
+    /// Candidates considered per point, closest first.
+    pub max_candidates: usize,
+    /// Standard deviation of the GPS noise.
+    pub gps_sigma_m: f64,
+    /// How much the route distance between consecutive matches may differ from the distance
+    /// between their GPS points. Higher values allow more detours.
+    pub beta_m: f64,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            search_radius_m: 50.0,
+            max_candidates: 8,
+            gps_sigma_m: 10.0,
+            beta_m: 10.0,
+        }
+    }
+}
+
+/// The position on a way a GPS point was matched to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedPoint {
+    pub way_id: u64,
+    pub lat: f64,
+    pub lon: f64,
+    /// Distance from the GPS point to the matched position.
+    pub distance_m: f64,
+    /// Posterior probability of the match, from 0 to 1.
+    pub confidence: f64,
+}
+
+/// Result of [`Router::match_trace`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceMatch {
+    /// The match of each input point, or `None` where no way was close enough.
+    pub points: Vec<Option<MatchedPoint>>,
+    /// The ways travelled along the matched path, with consecutive repeats removed.
+    pub way_ids: Vec<u64>,
+}
+
+struct Candidate {
+    snap: Snap,
+    distance_m: f64,
+    /// Log-probability of observing the GPS point from this candidate, up to a constant.
+    emission: f64,
+}
+
+struct Transition {
+    log_p: f64,
+    /// Way indexes along the route between the two candidates.
+    ways: Vec<u32>,
+}
+
+/// A point with candidates, together with the transitions into them from the previous point
+/// that had candidates.
+struct Step {
+    point: usize,
+    candidates: Vec<Candidate>,
+    /// `transitions[i][j]` leads from candidate `i` of the previous step to candidate `j`.
+    transitions: Vec<Vec<Option<Transition>>>,
+}
+
+impl<'a> Router<'a> {
+    /// Matches a GPS trace of `(lat, lon, unix timestamp)` points to the ways the router's profile
+    /// may use, with the default [`MatchOptions`].
+    pub fn match_trace(&self, points: &[(f64, f64, i64)]) -> Result<TraceMatch, TosmError> {
+        self.match_trace_with(points, &MatchOptions::default())
+    }
+
+    /// Like [`Router::match_trace`]. When no route connects the candidates of consecutive
+    /// points, the trace is split there and each part is matched on its own.
+    pub fn match_trace_with(
+        &self,
+        points: &[(f64, f64, i64)],
+        options: &MatchOptions,
+    ) -> Result<TraceMatch, TosmError> {
+        let mut steps: Vec<Step> = vec![];
+        for (index, &(lat, lon, _)) in points.iter().enumerate() {
+            let found = self
+                .file
+                .ways_within_where(lat, lon, options.search_radius_m, |way| {
+                    self.snappable[self.file.way_indexes[&way.id()]]
+                })?;
+            let candidates: Vec<Candidate> = found
+                .iter()
+                .take(options.max_candidates)
+                .filter_map(|m| {
+                    Some(Candidate {
+                        snap: Snap::new(self.file, &self.graph, m)?,
+                        distance_m: m.distance_m,
+                        emission: -0.5 * (m.distance_m / options.gps_sigma_m).powi(2),
+                    })
+                })
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+
+            let transitions = match steps.last() {
+                Some(prev) => self.transitions(
+                    points[prev.point],
+                    points[index],
+                    &prev.candidates,
+                    &candidates,
+                    options,
+                ),
+                None => vec![],
+            };
+            steps.push(Step {
+                point: index,
+                candidates,
+                transitions,
+            });
+        }
+
+        let chosen = decode(&steps);
+
+        let mut result = TraceMatch {
+            points: vec![None; points.len()],
+            way_ids: vec![],
+        };
+        let mut ways: Vec<u32> = vec![];
+        for (s, step) in steps.iter().enumerate() {
+            let (choice, confidence, start) = chosen[s];
+            let candidate = &step.candidates[choice];
+            result.points[step.point] = Some(MatchedPoint {
+                way_id: self.way_id(candidate.snap.way),
+                lat: candidate.snap.point[0],
+                lon: candidate.snap.point[1],
+                distance_m: candidate.distance_m,
+                confidence,
+            });
+
+            if start {
+                ways.push(candidate.snap.way);
+            } else if let Some(transition) = &step.transitions[chosen[s - 1].0][choice] {
+                ways.extend(&transition.ways);
+            }
+        }
+        ways.dedup();
+        result.way_ids = ways.into_iter().map(|way| self.way_id(way)).collect();
+
+        Ok(result)
+    }
+
+    /// Transitions between the candidates of two consecutive points. Routes longer than the
+    /// distance the profile could cover in the time between the points are ruled out.
+    fn transitions(
+        &self,
+        from_point: (f64, f64, i64),
+        to_point: (f64, f64, i64),
+        from: &[Candidate],
+        to: &[Candidate],
+        options: &MatchOptions,
+    ) -> Vec<Vec<Option<Transition>>> {
+        let straight = distance_m([from_point.0, from_point.1], [to_point.0, to_point.1]);
+        let elapsed = to_point.2.saturating_sub(from_point.2) as f64;
+        let reach = if elapsed > 0.0 {
+            (elapsed * self.graph.max_speed_mps * 1.5).max(straight)
+        } else {
+            straight * 2.0
+        };
+        let limit = reach + 2.0 * options.search_radius_m;
+
+        from.iter()
+            .map(|a| {
+                let tree = self.distances_from(&a.snap, limit);
+                to.iter()
+                    .map(|b| {
+                        let mut best = a.snap.direct_to(&b.snap).map(|(_, d)| (d, None));
+                        for leg in b.snap.exits() {
+                            if let Some(&(d, _)) = tree.get(&leg.vertex) {
+                                let total = d + leg.distance_m;
+                                if total <= limit && best.is_none_or(|(b, _)| total < b) {
+                                    best = Some((total, Some(leg.vertex)));
+                                }
+                            }
+                        }
+
+                        best.map(|(route, last)| {
+                            let mut ways = vec![a.snap.way];
+                            if let Some(vertex) = last {
+                                ways.extend(self.path_ways(&tree, vertex));
+                            }
+                            ways.push(b.snap.way);
+                            Transition {
+                                log_p: -(route - straight).abs() / options.beta_m,
+                                ways,
+                            }
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Dijkstra by distance from a snapped point, up to `limit` metres. Returns the distance
+    /// and parent edge of every vertex reached.
+    fn distances_from(&self, snap: &Snap, limit: f64) -> HashMap<u32, (f64, u32)> {
+        let mut tree: HashMap<u32, (f64, u32)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for leg in snap.entries() {
+            if tree
+                .get(&leg.vertex)
+                .is_none_or(|&(d, _)| leg.distance_m < d)
+            {
+                tree.insert(leg.vertex, (leg.distance_m, NO_EDGE));
+                heap.push(HeapEntry {
+                    cost: leg.distance_m,
+                    vertex: leg.vertex,
+                });
+            }
+        }
+
+        while let Some(HeapEntry { cost, vertex }) = heap.pop() {
+            if cost > limit {
+                break;
+            }
+            if cost > tree[&vertex].0 {
+                continue;
+            }
+            for (edge_index, edge) in self.graph.edges_from(vertex) {
+                let next = cost + edge.distance_m;
+                if tree.get(&edge.target).is_none_or(|&(d, _)| next < d) {
+                    tree.insert(edge.target, (next, edge_index));
+                    heap.push(HeapEntry {
+                        cost: next,
+                        vertex: edge.target,
+                    });
+                }
+            }
+        }
+        tree
+    }
+
+    /// Way indexes of the edges leading to `vertex` in a tree from [`Router::distances_from`].
+    fn path_ways(&self, tree: &HashMap<u32, (f64, u32)>, mut vertex: u32) -> Vec<u32> {
+        let mut ways = vec![];
+        while let Some(&(_, edge_index)) = tree.get(&vertex) {
+            if edge_index == NO_EDGE {
+                break;
+            }
+            ways.push(self.graph.edges[edge_index as usize].way);
+            vertex = self.graph.edge_source(edge_index);
+        }
+        ways.reverse();
+        ways
+    }
+}
+
+/// Picks a candidate for every step with Viterbi and computes its posterior probability with
+/// the forward-backward algorithm. A step none of whose candidates can be reached starts a new
+/// segment of the trace, which is flagged in the result.
+fn decode(steps: &[Step]) -> Vec<(usize, f64, bool)> {
+    let n = steps.len();
+    let mut viterbi: Vec<Vec<f64>> = Vec::with_capacity(n);
+    let mut back: Vec<Vec<usize>> = Vec::with_capacity(n);
+    let mut alpha: Vec<Vec<f64>> = Vec::with_capacity(n);
+    let mut starts = vec![false; n];
+
+    for (s, step) in steps.iter().enumerate() {
+        let mut scores = vec![f64::NEG_INFINITY; step.candidates.len()];
+        let mut parents = vec![0; step.candidates.len()];
+        let mut forward = vec![f64::NEG_INFINITY; step.candidates.len()];
+
+        if s > 0 {
+            for (j, candidate) in step.candidates.iter().enumerate() {
+                let incoming: Vec<(usize, f64)> = step
+                    .transitions
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, row)| Some((i, row[j].as_ref()?.log_p)))
+                    .collect();
+                for &(i, log_p) in &incoming {
+                    let score = viterbi[s - 1][i] + log_p + candidate.emission;
+                    if score > scores[j] {
+                        scores[j] = score;
+                        parents[j] = i;
+                    }
+                }
+                forward[j] = candidate.emission
+                    + log_sum_exp(incoming.iter().map(|&(i, log_p)| alpha[s - 1][i] + log_p));
+            }
+        }
+
+        if scores.iter().all(|score| score.is_infinite()) {
+            starts[s] = true;
+            scores = step.candidates.iter().map(|c| c.emission).collect();
+            forward = scores.clone();
+        }
+        viterbi.push(scores);
+        back.push(parents);
+        alpha.push(forward);
+    }
+
+    let mut beta: Vec<Vec<f64>> = steps
+        .iter()
+        .map(|s| vec![0.0; s.candidates.len()])
+        .collect();
+    for s in (0..n.saturating_sub(1)).rev() {
+        if starts[s + 1] {
+            continue;
+        }
+        let next = &steps[s + 1];
+        for i in 0..steps[s].candidates.len() {
+            beta[s][i] = log_sum_exp(next.candidates.iter().enumerate().filter_map(|(j, c)| {
+                let log_p = next.transitions[i][j].as_ref()?.log_p;
+                Some(log_p + c.emission + beta[s + 1][j])
+            }));
+        }
+    }
+
+    let argmax = |scores: &[f64]| {
+        (0..scores.len())
+            .max_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+            .unwrap_or(0)
+    };
+
+    let mut chosen = vec![(0, 0.0, false); n];
+    let mut end = n;
+    while end > 0 {
+        let start = (0..end).rev().find(|&s| starts[s]).unwrap_or(0);
+        let total = log_sum_exp(alpha[end - 1].iter().copied());
+
+        let mut choice = argmax(&viterbi[end - 1]);
+        for s in (start..end).rev() {
+            let confidence = (alpha[s][choice] + beta[s][choice] - total).exp();
+            chosen[s] = (choice, confidence.clamp(0.0, 1.0), s == start);
+            choice = back[s][choice];
+        }
+        end = start;
+    }
+    chosen
+}
+
+fn log_sum_exp(values: impl Iterator<Item = f64>) -> f64 {
+    let values: Vec<f64> = values.collect();
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max.is_infinite() {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::tests::grid;
+    use crate::Router;
+
+    #[test]
+    fn matches_noisy_traces() {
+        let file = grid();
+        let router = Router::new(&file);
+
+        // Eastbound along Neðri, up Austur and west along Efri, with a glitch in between.
+        let trace = [
+            (63.99998, -20.9995, 0),
+            (64.00002, -20.9985, 5),
+            (64.5, -20.0, 10),
+            (63.99997, -20.9970, 15),
+            (64.0005, -20.99602, 25),
+            (64.00103, -20.9970, 35),
+        ];
+        let matched = router.match_trace(&trace).unwrap();
+
+        assert_eq!(matched.way_ids, vec![10, 11, 15, 12]);
+        assert!(matched.points[2].is_none());
+
+        let ways: Vec<u64> = matched.points.iter().flatten().map(|p| p.way_id).collect();
+        assert_eq!(ways, vec![10, 10, 11, 15, 12]);
+        for point in matched.points.iter().flatten() {
+            assert!(point.distance_m < 5.0);
+            assert!(point.confidence > 0.5 && point.confidence <= 1.0);
+        }
+        let first = matched.points[0].as_ref().unwrap();
+        assert!((first.lat - 64.0).abs() < 1e-9);
+
+        // Timestamps far apart must not overflow.
+        let trace = [
+            (63.99998, -20.9995, i64::MIN),
+            (64.00002, -20.9985, i64::MAX),
+        ];
+        let matched = router.match_trace(&trace).unwrap();
+        assert_eq!(matched.way_ids, vec![10]);
+        let trace = [
+            (63.99998, -20.9995, i64::MAX),
+            (64.00002, -20.9985, i64::MIN),
+        ];
+        assert_eq!(router.match_trace(&trace).unwrap().way_ids, vec![10]);
+    }
+}
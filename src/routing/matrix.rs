@@ -0,0 +1,217 @@
+//! Many-to-many travel costs.
+
+use std::collections::{BinaryHeap, HashMap};
+
+use super::{HeapEntry, Leg, Router, Snap};
+use crate::TosmError;
+
+/// Result of [`Router::distance_matrix`]. A cell is `None` when the target can't be reached
+/// from the source, or when either coordinate is too far from any usable way to be snapped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceMatrix {
+    /// `durations_s[i][j]` is the travel time from source `i` to target `j` in seconds.
+    pub durations_s: Vec<Vec<Option<f64>>>,
+    /// Length of the same routes in metres.
+    pub distances_m: Vec<Vec<Option<f64>>>,
+}
+
+impl DistanceMatrix {
+    fn offer(&mut self, i: usize, j: usize, cost: f64, distance_m: f64) {
+        if self.durations_s[i][j].is_none_or(|best| cost < best) {
+            self.durations_s[i][j] = Some(cost);
+            self.distances_m[i][j] = Some(distance_m);
+        }
+    }
+}
+
+impl<'a> Router<'a> {
+    /// Travel costs between every pair of `(lat, lon)` sources and targets, snapped onto ways as
+    /// in [`Router::route_between`].
+    ///
+    /// With a contraction hierarchy the targets' backward searches are stored in buckets that
+    /// every source's forward search scans; without one, each source runs a single search that
+    /// stops once all targets are settled.
+    pub fn distance_matrix(
+        &self,
+        sources: &[(f64, f64)],
+        targets: &[(f64, f64)],
+    ) -> Result<DistanceMatrix, TosmError> {
+        let snap_all = |points: &[(f64, f64)]| -> Result<Vec<Option<Snap>>, TosmError> {
+            points
+                .iter()
+                .map(|&(lat, lon)| self.snap(lat, lon))
+                .collect()
+        };
+        let sources = snap_all(sources)?;
+        let targets = snap_all(targets)?;
+
+        let mut matrix = DistanceMatrix {
+            durations_s: vec![vec![None; targets.len()]; sources.len()],
+            distances_m: vec![vec![None; targets.len()]; sources.len()],
+        };
+
+        for (i, source) in sources.iter().enumerate() {
+            for (j, target) in targets.iter().enumerate() {
+                if let (Some(source), Some(target)) = (source, target) {
+                    if let Some((cost, distance_m)) = source.direct_to(target) {
+                        matrix.offer(i, j, cost, distance_m);
+                    }
+                }
+            }
+        }
+
+        let exits: Vec<Vec<Leg>> = targets
+            .iter()
+            .map(|t| t.as_ref().map_or(vec![], Snap::exits))
+            .collect();
+
+        match self.hierarchy {
+            Some(ch) => {
+                let mut buckets: HashMap<u32, Vec<(usize, f64, f64)>> = HashMap::new();
+                for (j, legs) in exits.iter().enumerate() {
+                    for (vertex, cost, distance_m) in ch.upward(legs, false) {
+                        buckets
+                            .entry(vertex)
+                            .or_default()
+                            .push((j, cost, distance_m));
+                    }
+                }
+
+                for (i, source) in sources.iter().enumerate() {
+                    let entries = source.as_ref().map_or(vec![], Snap::entries);
+                    for (vertex, cost, distance_m) in ch.upward(&entries, true) {
+                        for &(j, rest, rest_m) in buckets.get(&vertex).into_iter().flatten() {
+                            matrix.offer(i, j, cost + rest, distance_m + rest_m);
+                        }
+                    }
+                }
+            }
+            None => {
+                let mut by_vertex: HashMap<u32, Vec<(usize, Leg)>> = HashMap::new();
+                for (j, legs) in exits.iter().enumerate() {
+                    for &leg in legs {
+                        by_vertex.entry(leg.vertex).or_default().push((j, leg));
+                    }
+                }
+
+                for (i, source) in sources.iter().enumerate() {
+                    let entries = source.as_ref().map_or(vec![], Snap::entries);
+                    for (vertex, cost, distance_m) in self.one_to_many(&entries, &by_vertex) {
+                        for &(j, leg) in &by_vertex[&vertex] {
+                            matrix.offer(i, j, cost + leg.weight, distance_m + leg.distance_m);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(matrix)
+    }
+
+    /// Dijkstra from `sources` until every vertex in `targets` is settled. Returns the settled
+    /// target vertices with their cost and distance.
+    fn one_to_many<T>(&self, sources: &[Leg], targets: &HashMap<u32, T>) -> Vec<(u32, f64, f64)> {
+        let mut dist: HashMap<u32, (f64, f64)> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for leg in sources {
+            if dist.get(&leg.vertex).is_none_or(|&(d, _)| leg.weight < d) {
+                dist.insert(leg.vertex, (leg.weight, leg.distance_m));
+                heap.push(HeapEntry {
+                    cost: leg.weight,
+                    vertex: leg.vertex,
+                });
+            }
+        }
+
+        let mut found = vec![];
+        while let Some(HeapEntry { cost, vertex }) = heap.pop() {
+            let (best, distance_m) = dist[&vertex];
+            if cost > best {
+                continue;
+            }
+            if targets.contains_key(&vertex) {
+                found.push((vertex, cost, distance_m));
+                if found.len() == targets.len() {
+                    break;
+                }
+            }
+
+            for (_, edge) in self.graph.edges_from(vertex) {
+                let next = cost + edge.weight;
+                if dist.get(&edge.target).is_none_or(|&(d, _)| next < d) {
+                    dist.insert(edge.target, (next, distance_m + edge.distance_m));
+                    heap.push(HeapEntry {
+                        cost: next,
+                        vertex: edge.target,
+                    });
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::tests::grid;
+    use crate::{Profile, Router, TOSMFile};
+
+    #[test]
+    fn computes_many_to_many_costs() {
+        let points = [
+            (64.0, -20.9995),
+            (64.0, -20.9985),
+            (64.001, -20.997),
+            (64.0, -20.996),
+        ];
+
+        let plain_file = grid();
+        let mut file = grid();
+        file.build_contraction_hierarchy(Profile::Car);
+
+        for file in [&plain_file, &file] {
+            let router = Router::new(file);
+            let matrix = router.distance_matrix(&points, &points).unwrap();
+
+            for (i, from) in points.iter().enumerate() {
+                for (j, to) in points.iter().enumerate() {
+                    let route = router
+                        .route_between(from.0, from.1, to.0, to.1)
+                        .unwrap()
+                        .unwrap();
+                    let duration = matrix.durations_s[i][j].unwrap();
+                    let distance = matrix.distances_m[i][j].unwrap();
+                    assert!((duration - route.duration_s).abs() < 1e-6, "{i} -> {j}");
+                    assert!((distance - route.distance_m).abs() < 1e-6, "{i} -> {j}");
+                }
+            }
+            assert_eq!(matrix.durations_s[2][2], Some(0.0));
+        }
+
+        // A one-way dead end and a separate street.
+        let file = TOSMFile::from_json_str(
+            r#"{
+                "nodes": [
+                    {"id": 1, "lat": 64.00, "lon": -21.000},
+                    {"id": 2, "lat": 64.00, "lon": -20.999},
+                    {"id": 3, "lat": 64.01, "lon": -21.000},
+                    {"id": 4, "lat": 64.01, "lon": -20.999}
+                ],
+                "ways": [
+                    {"id": 10, "node_ids": [1, 2], "one_way": true, "name": null},
+                    {"id": 11, "node_ids": [3, 4], "one_way": false, "name": null}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let matrix = Router::new(&file)
+            .distance_matrix(
+                &[(64.0, -20.9995)],
+                &[(64.0, -20.9992), (64.0, -20.9998), (64.01, -20.9995)],
+            )
+            .unwrap();
+        assert!(matrix.durations_s[0][0].is_some());
+        assert_eq!(matrix.durations_s[0][1], None);
+        assert_eq!(matrix.distances_m[0][2], None);
+    }
+}
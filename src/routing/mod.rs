@@ -0,0 +1,520 @@
+//! Shortest-path routing over the road graph formed by the ways of a [`TOSMFile`].
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::geo::distance_m;
+use crate::{check_coordinate, TOSMFile, TosmError};
+
+mod ch;
+mod graph;
+mod instructions;
+mod isochrone;
+mod matching;
+mod matrix;
+mod profile;
+mod snap;
+mod turns;
+
+pub(crate) use ch::ContractionHierarchy;
+pub(crate) use graph::Graph;
+use instructions::Segment;
+pub use instructions::{Instruction, Maneuver};
+pub use isochrone::{Isochrone, ReachedNode, ReachedWay};
+pub use matching::{MatchOptions, MatchedPoint, TraceMatch};
+pub use matrix::DistanceMatrix;
+pub use profile::Profile;
+use snap::{Leg, Snap};
+
+const NO_EDGE: u32 = u32::MAX;
+
+/// Ways in weakly connected components with fewer vertices than this are not used for
+/// snapping coordinates, unless the whole graph is that small. This keeps endpoints off
+/// isolated footpaths and parking aisles that cannot reach the rest of the network.
+const MIN_SNAP_COMPONENT: usize = 1000;
+
+/// A path through the road graph.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Route {
+    /// The graph nodes passed through. Snapped start and end points are not nodes and only
+    /// appear in `geometry`.
+    pub node_ids: Vec<u64>,
+    /// The ways travelled, in order, with consecutive repeats removed.
+    pub way_ids: Vec<u64>,
+    /// `[lat, lon]` points of the route from start to end.
+    pub geometry: Vec<[f64; 2]>,
+    pub distance_m: f64,
+    /// Estimated travel time for the router's profile.
+    pub duration_s: f64,
+    /// Turn-by-turn steps from departure to arrival.
+    pub instructions: Vec<Instruction>,
+}
+
+/// Finds the fastest routes between nodes or coordinates of a file for a [`Profile`]. Building
+/// the router constructs the road graph once, so reuse it across queries. Queries use the file's
+/// contraction hierarchy for the profile when it has one (see
+/// [`TOSMFile::build_contraction_hierarchy`]).
+pub struct Router<'a> {
+    file: &'a TOSMFile,
+    graph: Graph,
+    hierarchy: Option<&'a ContractionHierarchy>,
+    /// Per way index, whether coordinates may be snapped onto the way.
+    snappable: Vec<bool>,
+}
+
+/// The vertices and edges of a search result, from the source vertex to the exit vertex.
+pub(crate) struct Path {
+    vertices: Vec<u32>,
+    edges: Vec<u32>,
+    /// Index of the source leg the path starts from and of the target leg it ends with.
+    entry: usize,
+    exit: usize,
+}
+
+impl<'a> Router<'a> {
+    /// A router for [`Profile::Car`].
+    pub fn new(file: &'a TOSMFile) -> Self {
+        Self::with_profile(file, Profile::Car)
+    }
+
+    pub fn with_profile(file: &'a TOSMFile, profile: Profile) -> Self {
+        let graph = Graph::new(file, profile);
+        let snappable = snappable_ways(file, &graph);
+        let hierarchy = file
+            .contraction_hierarchies
+            .iter()
+            .find(|ch| ch.fits(&graph));
+
+        Router {
+            file,
+            graph,
+            hierarchy,
+            snappable,
+        }
+    }
+
+    /// Finds the fastest route between two nodes that obeys the turn restrictions for the
+    /// router's profile. Returns `None` when the target cannot be reached.
+    pub fn route(&self, from_node: u64, to_node: u64) -> Result<Option<Route>, TosmError> {
+        let source = self.vertex(from_node)?;
+        let target = self.vertex(to_node)?;
+
+        let sources = [Leg {
+            vertex: source,
+            weight: 0.0,
+            distance_m: 0.0,
+        }];
+        let targets: Vec<Leg> = self
+            .graph
+            .vertices_of(target)
+            .map(|vertex| Leg {
+                vertex,
+                ..sources[0]
+            })
+            .collect();
+        let goal = self.graph.coords[target as usize];
+
+        Ok(self
+            .search(&sources, &targets, goal)
+            .map(|path| self.build_route(&path, None, None)))
+    }
+
+    /// Routes between two coordinates. Each end is snapped onto the closest point of a way
+    /// segment, and the route starts and ends at those points rather than at the nearest nodes.
+    /// Returns `None` when no way is found or the end cannot be reached from the start.
+    pub fn route_between(
+        &self,
+        lat1: f64,
+        lon1: f64,
+        lat2: f64,
+        lon2: f64,
+    ) -> Result<Option<Route>, TosmError> {
+        let (start, end) = match (self.snap(lat1, lon1)?, self.snap(lat2, lon2)?) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(None),
+        };
+
+        let direct = start.direct_to(&end).map(|(weight, distance_m)| {
+            let geometry = vec![start.point, end.point];
+            let segment = Segment {
+                way: start.way,
+                distance_m,
+                duration_s: weight,
+            };
+            let route = Route {
+                node_ids: vec![],
+                way_ids: vec![self.way_id(start.way)],
+                instructions: self.instructions(&geometry, &[None, None], &[segment]),
+                geometry,
+                distance_m,
+                duration_s: weight,
+            };
+            (weight, route)
+        });
+
+        let via_graph = self
+            .search(&start.entries(), &end.exits(), end.point)
+            .map(|path| {
+                let entry = start.entries()[path.entry];
+                let exit = end.exits()[path.exit];
+                let route = self.build_route(&path, Some((&start, entry)), Some((&end, exit)));
+                let weight = entry.weight + self.path_weight(&path) + exit.weight;
+                (weight, route)
+            });
+
+        Ok(match (direct, via_graph) {
+            (Some(a), Some(b)) => Some(if a.0 <= b.0 { a.1 } else { b.1 }),
+            (a, b) => a.or(b).map(|(_, route)| route),
+        })
+    }
+
+    pub(crate) fn snap(&self, lat: f64, lon: f64) -> Result<Option<Snap>, TosmError> {
+        check_coordinate(None, lat, lon)?;
+        let found = self
+            .file
+            .nearest_way_where(lat, lon, f64::INFINITY, |way| {
+                let index = self.file.way_indexes[&way.id()];
+                self.snappable[index]
+            })?;
+
+        Ok(found.and_then(|m| Snap::new(self.file, &self.graph, &m)))
+    }
+
+    fn vertex(&self, node_id: u64) -> Result<u32, TosmError> {
+        self.graph
+            .vertices
+            .get(&node_id)
+            .copied()
+            .ok_or(TosmError::UnknownNode(node_id))
+    }
+
+    fn way_id(&self, way: u32) -> u64 {
+        self.file.ways()[way as usize].id()
+    }
+
+    /// A lower bound of the travel time from `vertex` to `goal`.
+    fn heuristic(&self, vertex: u32, goal: [f64; 2]) -> f64 {
+        distance_m(self.graph.coords[vertex as usize], goal) / self.graph.max_speed_mps
+    }
+
+    /// Finds the cheapest path from several source vertices, each with an initial cost, to
+    /// several target vertices, each with a cost for the final leg.
+    fn search(&self, sources: &[Leg], targets: &[Leg], goal: [f64; 2]) -> Option<Path> {
+        match self.hierarchy {
+            Some(ch) => ch.query(&self.graph, sources, targets),
+            None => self.astar(sources, targets, goal),
+        }
+    }
+
+    /// [`Router::search`] without a contraction hierarchy. `goal` is the point all targets
+    /// lead to and guides the search.
+    fn astar(&self, sources: &[Leg], targets: &[Leg], goal: [f64; 2]) -> Option<Path> {
+        let n = self.graph.len();
+        let mut dist = vec![f64::INFINITY; n];
+        let mut parent = vec![NO_EDGE; n];
+        let mut settled = vec![false; n];
+        let mut heap = BinaryHeap::new();
+
+        let exits: HashMap<u32, usize> = targets
+            .iter()
+            .enumerate()
+            .map(|(i, leg)| (leg.vertex, i))
+            .collect();
+
+        for leg in sources {
+            if leg.weight < dist[leg.vertex as usize] {
+                dist[leg.vertex as usize] = leg.weight;
+                heap.push(HeapEntry {
+                    cost: leg.weight + self.heuristic(leg.vertex, goal),
+                    vertex: leg.vertex,
+                });
+            }
+        }
+
+        let mut best: Option<(f64, u32, usize)> = None;
+        while let Some(HeapEntry { cost, vertex }) = heap.pop() {
+            if best.is_some_and(|(total, _, _)| cost >= total) {
+                break;
+            }
+            if settled[vertex as usize] {
+                continue;
+            }
+            settled[vertex as usize] = true;
+
+            let d = dist[vertex as usize];
+            if let Some(&exit) = exits.get(&vertex) {
+                let total = d + targets[exit].weight;
+                if best.is_none_or(|(b, _, _)| total < b) {
+                    best = Some((total, vertex, exit));
+                }
+            }
+
+            for (edge_index, edge) in self.graph.edges_from(vertex) {
+                let next = d + edge.weight;
+                if next < dist[edge.target as usize] {
+                    dist[edge.target as usize] = next;
+                    parent[edge.target as usize] = edge_index;
+                    heap.push(HeapEntry {
+                        cost: next + self.heuristic(edge.target, goal),
+                        vertex: edge.target,
+                    });
+                }
+            }
+        }
+
+        let (_, last, exit) = best?;
+        let mut vertices = vec![last];
+        let mut edges = vec![];
+        let mut v = last;
+        while parent[v as usize] != NO_EDGE {
+            let edge_index = parent[v as usize];
+            edges.push(edge_index);
+            v = self.graph.edge_source(edge_index);
+            vertices.push(v);
+        }
+        vertices.reverse();
+        edges.reverse();
+
+        let entry = sources.iter().position(|leg| leg.vertex == v)?;
+        Some(Path {
+            vertices,
+            edges,
+            entry,
+            exit,
+        })
+    }
+
+    fn path_weight(&self, path: &Path) -> f64 {
+        path.edges
+            .iter()
+            .map(|&e| self.graph.edges[e as usize].weight)
+            .sum()
+    }
+
+    /// Assembles a route from a search path, adding the partial segments to and from snapped
+    /// points when given.
+    fn build_route(
+        &self,
+        path: &Path,
+        start: Option<(&Snap, Leg)>,
+        end: Option<(&Snap, Leg)>,
+    ) -> Route {
+        let mut route = Route {
+            node_ids: vec![],
+            way_ids: vec![],
+            geometry: vec![],
+            distance_m: 0.0,
+            duration_s: 0.0,
+            instructions: vec![],
+        };
+        // Per geometry point, the node it is, and the segments between consecutive points.
+        let mut nodes = vec![];
+        let mut segments = vec![];
+        let mut push_segment = |route: &mut Route, way: u32, distance_m: f64, weight: f64| {
+            route.distance_m += distance_m;
+            route.duration_s += weight;
+            let way_id = self.way_id(way);
+            if route.way_ids.last() != Some(&way_id) {
+                route.way_ids.push(way_id);
+            }
+            segments.push(Segment {
+                way,
+                distance_m,
+                duration_s: weight,
+            });
+        };
+
+        if let Some((snap, leg)) = start {
+            route.geometry.push(snap.point);
+            nodes.push(None);
+            push_segment(&mut route, snap.way, leg.distance_m, leg.weight);
+        }
+
+        for &v in &path.vertices {
+            let node_id = self.graph.node_ids[v as usize];
+            route.node_ids.push(node_id);
+            route.geometry.push(self.graph.coords[v as usize]);
+            nodes.push(Some(node_id));
+        }
+        for &e in &path.edges {
+            let edge = &self.graph.edges[e as usize];
+            push_segment(&mut route, edge.way, edge.distance_m, edge.weight);
+        }
+
+        if let Some((snap, leg)) = end {
+            route.geometry.push(snap.point);
+            nodes.push(None);
+            push_segment(&mut route, snap.way, leg.distance_m, leg.weight);
+        }
+
+        route.instructions = self.instructions(&route.geometry, &nodes, &segments);
+        route
+    }
+}
+
+/// Marks the ways whose edges lie in a component large enough to snap onto.
+fn snappable_ways(file: &TOSMFile, graph: &Graph) -> Vec<bool> {
+    let components = graph.components();
+    let mut sizes: HashMap<u32, usize> = HashMap::new();
+    for &c in &components {
+        *sizes.entry(c).or_default() += 1;
+    }
+    let largest = sizes.values().copied().max().unwrap_or(0);
+    let min_size = MIN_SNAP_COMPONENT.min(largest);
+
+    let mut snappable = vec![false; file.ways().len()];
+    for v in 0..graph.len() as u32 {
+        if sizes[&components[v as usize]] < min_size {
+            continue;
+        }
+        for (_, edge) in graph.edges_from(v) {
+            snappable[edge.way as usize] = true;
+        }
+    }
+    snappable
+}
+
+/// Min-heap entry ordered by `cost`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct HeapEntry {
+    pub cost: f64,
+    pub vertex: u32,
+}
+
+impl Eq for HeapEntry {}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.vertex.cmp(&self.vertex))
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, Router};
+    use crate::{TOSMFile, TosmError};
+
+    /// A 3x2 grid of streets about 111 m apart, plus an isolated footpath (way 16) right next
+    /// to node 1:
+    ///
+    /// ```text
+    /// 4 --- 5 --- 6
+    /// |     |     |
+    /// 1 --> 2 --- 3
+    /// 8 - 9
+    /// ```
+    ///
+    /// The bottom street (way 10) is one-way eastbound between 1 and 2.
+    pub(crate) fn grid() -> TOSMFile {
+        grid_with_relations("[]")
+    }
+
+    /// [`grid`] with the given JSON array of relations.
+    pub(crate) fn grid_with_relations(relations: &str) -> TOSMFile {
+        let source = r#"{
+                "nodes": [
+                    {"id": 1, "lat": 64.000, "lon": -21.000},
+                    {"id": 2, "lat": 64.000, "lon": -20.998},
+                    {"id": 3, "lat": 64.000, "lon": -20.996},
+                    {"id": 4, "lat": 64.001, "lon": -21.000},
+                    {"id": 5, "lat": 64.001, "lon": -20.998},
+                    {"id": 6, "lat": 64.001, "lon": -20.996},
+                    {"id": 7, "lat": 65.000, "lon": -20.000},
+                    {"id": 8, "lat": 63.9999, "lon": -21.000},
+                    {"id": 9, "lat": 63.9999, "lon": -20.999}
+                ],
+                "ways": [
+                    {"id": 10, "node_ids": [1, 2], "one_way": true, "name": "Neðri"},
+                    {"id": 11, "node_ids": [2, 3], "one_way": false, "name": "Neðri"},
+                    {"id": 12, "node_ids": [4, 5, 6], "one_way": false, "name": "Efri"},
+                    {"id": 13, "node_ids": [1, 4], "one_way": false, "name": "Vestur"},
+                    {"id": 14, "node_ids": [2, 5], "one_way": false, "name": "Mið"},
+                    {"id": 15, "node_ids": [3, 6], "one_way": false, "name": "Austur"},
+                    {"id": 16, "node_ids": [8, 9], "one_way": false, "name": null}
+                ],
+                "relations": RELATIONS
+            }"#;
+        TOSMFile::from_json_str(&source.replace("RELATIONS", relations)).unwrap()
+    }
+
+    #[test]
+    fn routes_along_one_way_streets() {
+        let file = grid();
+        let router = Router::new(&file);
+
+        let route = router.route(1, 3).unwrap().unwrap();
+        assert_eq!(route.node_ids, vec![1, 2, 3]);
+        assert_eq!(route.way_ids, vec![10, 11]);
+        assert!((route.distance_m - 195.1).abs() < 1.0);
+
+        // Going back west has to avoid the one-way segment.
+        let route = router.route(2, 1).unwrap().unwrap();
+        assert_eq!(route.node_ids, vec![2, 5, 4, 1]);
+        assert_eq!(route.way_ids, vec![14, 12, 13]);
+
+        assert_eq!(router.route(3, 3).unwrap().unwrap().node_ids, vec![3]);
+        assert!(router.route(1, 8).unwrap().is_none());
+        assert!(matches!(router.route(1, 7), Err(TosmError::UnknownNode(7))));
+    }
+
+    #[test]
+    fn routes_between_snapped_coordinates() {
+        let file = grid();
+        let router = Router::new(&file);
+
+        // Start halfway along the one-way street, between it and the footpath, which is
+        // closer but not connected to the streets. End halfway along Efri.
+        let route = router
+            .route_between(63.99993, -20.999, 64.001, -20.997)
+            .unwrap()
+            .unwrap();
+        assert_eq!(route.node_ids, vec![2, 5]);
+        assert_eq!(route.way_ids, vec![10, 14, 12]);
+        assert_eq!(route.geometry.len(), 4);
+        assert!((route.geometry[0][0] - 64.0).abs() < 1e-9);
+        assert!((route.distance_m - (48.8 + 111.2 + 48.8)).abs() < 1.0);
+
+        // Both ends on the same segment, in and against the one-way direction.
+        let route = router
+            .route_between(64.0, -20.9995, 64.0, -20.9985)
+            .unwrap()
+            .unwrap();
+        assert!(route.node_ids.is_empty());
+        assert_eq!(route.way_ids, vec![10]);
+        assert!((route.distance_m - 48.8).abs() < 1.0);
+
+        let route = router
+            .route_between(64.0, -20.9985, 64.0, -20.9995)
+            .unwrap()
+            .unwrap();
+        assert_eq!(route.node_ids, vec![2, 5, 4, 1]);
+    }
+
+    #[test]
+    fn routes_by_profile() {
+        let file = grid();
+
+        // Untagged ways are residential streets at 40 km/h.
+        let route = Router::new(&file).route(1, 3).unwrap().unwrap();
+        assert!((route.duration_s - route.distance_m / (40.0 / 3.6)).abs() < 1e-6);
+
+        // Pedestrians may walk against the one-way street.
+        let route = Router::with_profile(&file, Profile::Foot)
+            .route(2, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(route.node_ids, vec![2, 1]);
+        assert!((route.duration_s - 70.2).abs() < 0.1);
+    }
+}
@@ -0,0 +1,200 @@
+//! Travel modes and how they rate ways.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Way;
+
+/// A travel mode, deciding which ways may be used, in which direction and how fast.
+///
+/// Ways of OSM sources without a `highway` tag, such as building outlines, can't be used. Only
+/// the untagged ways of JSON sources are treated as ordinary roads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Profile {
+    #[default]
+    Car,
+    Bicycle,
+    Foot,
+}
+
+impl Profile {
+    /// Travel speed along the way in km/h, or `None` if the way may not be used.
+    pub(crate) fn speed_kmh(&self, way: &Way) -> Option<f64> {
+        let highway = way.highway();
+        if let Some("construction" | "proposed" | "abandoned" | "platform" | "raceway") = highway {
+            return None;
+        }
+        if highway.is_none() && way.tagged() {
+            return None;
+        }
+        // The most specific access tag present decides, so `access=no` with `foot=yes` is
+        // still open to pedestrians.
+        let access = self
+            .access_keys()
+            .iter()
+            .rev()
+            .find_map(|key| way.access(key));
+        if let Some("no" | "private" | "agricultural" | "forestry" | "emergency" | "psv") = access {
+            return None;
+        }
+
+        match self {
+            Profile::Car => {
+                let default = match highway {
+                    Some("motorway") => 110.0,
+                    Some("trunk") => 90.0,
+                    Some("primary") => 80.0,
+                    Some("secondary") => 70.0,
+                    Some("tertiary") => 60.0,
+                    Some("motorway_link" | "trunk_link") => 60.0,
+                    Some("primary_link" | "secondary_link" | "tertiary_link") => 50.0,
+                    Some("unclassified") => 50.0,
+                    Some("residential") | None => 40.0,
+                    Some("service" | "track") => 20.0,
+                    Some("living_street") => 10.0,
+                    Some(_) => return None,
+                };
+                let speed = way.maxspeed().and_then(parse_maxspeed).unwrap_or(default);
+                Some(speed * surface_factor(way.surface()))
+            }
+            Profile::Bicycle => {
+                let speed = match highway {
+                    Some("motorway" | "motorway_link" | "steps") => return None,
+                    // Cyclists are assumed to walk their bike where cycling may not be allowed.
+                    Some("footway" | "pedestrian") => 6.0,
+                    Some("track" | "path" | "bridleway") => 12.0,
+                    _ => 15.0,
+                };
+                Some(speed * surface_factor(way.surface()))
+            }
+            Profile::Foot => match highway {
+                Some("motorway" | "motorway_link") => None,
+                Some("steps") => Some(2.5),
+                _ => Some(5.0),
+            },
+        }
+    }
+
+    /// The access tags that apply to the mode, from the most general to the most specific.
+    fn access_keys(&self) -> &'static [&'static str] {
+        match self {
+            Profile::Car => &["access", "vehicle", "motor_vehicle", "motorcar"],
+            Profile::Bicycle => &["access", "vehicle", "bicycle"],
+            Profile::Foot => &["access", "foot"],
+        }
+    }
+
+    /// Whether the way may only be travelled in node order.
+    pub(crate) fn one_way(&self, way: &Way) -> bool {
+        match self {
+            Profile::Car => way.one_way(),
+            Profile::Bicycle => way.one_way() && !way.bicycle_contraflow(),
+            // One-way restrictions apply to vehicles, not pedestrians.
+            Profile::Foot => false,
+        }
+    }
+
+    /// An upper bound of [`Profile::speed_kmh`] over the given ways, used by the A* heuristic.
+    pub(crate) fn max_speed_kmh<'a>(&self, ways: impl IntoIterator<Item = &'a Way>) -> f64 {
+        ways.into_iter()
+            .filter_map(|way| self.speed_kmh(way))
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Parses a `maxspeed` value into km/h. Handles plain numbers, `mph` values and the `urban` and
+/// `rural` zones (`IS:urban`, `DE:rural`, ...); anything else is left to the highway default.
+fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.split(';').next()?.trim();
+    if let Some(mph) = value.strip_suffix("mph") {
+        return mph.trim().parse::<f64>().ok().map(|v| v * 1.609);
+    }
+    if value.ends_with(":urban") {
+        return Some(50.0);
+    }
+    if value.ends_with(":rural") {
+        return Some(80.0);
+    }
+    value
+        .trim_end_matches("km/h")
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| *v > 0.0)
+}
+
+/// Slows vehicles down on unpaved surfaces.
+fn surface_factor(surface: Option<&str>) -> f64 {
+    match surface {
+        Some("sand" | "mud" | "grass") => 0.5,
+        Some(
+            "unpaved" | "gravel" | "fine_gravel" | "compacted" | "dirt" | "ground" | "pebblestone",
+        ) => 0.75,
+        _ => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_maxspeed, Profile};
+    use crate::osm::way_from_tags;
+
+    #[test]
+    fn rates_ways_per_profile() {
+        let road = way_from_tags(
+            1,
+            vec![1, 2],
+            [
+                ("highway", "primary"),
+                ("maxspeed", "90"),
+                ("surface", "gravel"),
+                ("oneway", "yes"),
+                ("oneway:bicycle", "no"),
+            ],
+        );
+        assert_eq!(Profile::Car.speed_kmh(&road), Some(67.5));
+        assert_eq!(Profile::Bicycle.speed_kmh(&road), Some(11.25));
+        assert!(Profile::Car.one_way(&road));
+        assert!(!Profile::Bicycle.one_way(&road));
+        assert!(!Profile::Foot.one_way(&road));
+
+        let footway = way_from_tags(2, vec![1, 2], [("highway", "footway")]);
+        assert_eq!(Profile::Car.speed_kmh(&footway), None);
+        assert_eq!(Profile::Foot.speed_kmh(&footway), Some(5.0));
+
+        let motorway = way_from_tags(3, vec![1, 2], [("highway", "motorway")]);
+        assert_eq!(Profile::Foot.speed_kmh(&motorway), None);
+
+        let building = way_from_tags(4, vec![1, 2, 3, 1], [("building", "yes")]);
+        assert_eq!(Profile::Car.speed_kmh(&building), None);
+        assert_eq!(Profile::Foot.speed_kmh(&building), None);
+
+        let closed = way_from_tags(
+            5,
+            vec![1, 2],
+            [
+                ("highway", "residential"),
+                ("access", "no"),
+                ("foot", "yes"),
+            ],
+        );
+        assert_eq!(Profile::Car.speed_kmh(&closed), None);
+        assert_eq!(Profile::Bicycle.speed_kmh(&closed), None);
+        assert_eq!(Profile::Foot.speed_kmh(&closed), Some(5.0));
+
+        let private = way_from_tags(
+            6,
+            vec![1, 2],
+            [("highway", "service"), ("motor_vehicle", "private")],
+        );
+        assert_eq!(Profile::Car.speed_kmh(&private), None);
+        assert_eq!(Profile::Bicycle.speed_kmh(&private), Some(15.0));
+
+        let untagged = crate::Way::new(7, vec![1, 2], false, None);
+        assert_eq!(Profile::Car.speed_kmh(&untagged), Some(40.0));
+
+        assert_eq!(parse_maxspeed("30 mph").map(f64::round), Some(48.0));
+        assert_eq!(parse_maxspeed("IS:urban"), Some(50.0));
+        assert_eq!(parse_maxspeed("50;30"), Some(50.0));
+        assert_eq!(parse_maxspeed("signals"), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Virtual graph vertices at points snapped onto way segments.
+
+use super::Graph;
+use crate::{TOSMFile, WayMatch};
+
+/// A point projected onto a way segment.
+#[derive(Debug, Clone)]
+pub(crate) struct Snap {
+    pub point: [f64; 2],
+    pub way: u32,
+    pub segment: usize,
+    pub fraction: f64,
+    /// Travel along the segment in way order and against it, when the profile allows it.
+    pub forward: Option<Pass>,
+    pub backward: Option<Pass>,
+}
+
+/// Travel along a snapped segment in one direction, from its start node to its end node.
+#[derive(Debug, Clone)]
+pub(crate) struct Pass {
+    /// Weight and distance of the whole segment.
+    pub weight: f64,
+    pub distance_m: f64,
+    /// The vertex reached at the end node when leaving the snapped point this way.
+    pub entry: u32,
+    /// The vertices of the start node the segment may be entered from. Turn restrictions can
+    /// give a node several vertices, see [`Graph::vertices_of`].
+    pub exits: Vec<u32>,
+}
+
+/// Cost and distance of a partial segment.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Leg {
+    pub vertex: u32,
+    pub weight: f64,
+    pub distance_m: f64,
+}
+
+impl Snap {
+    pub fn new(file: &TOSMFile, graph: &Graph, m: &WayMatch) -> Option<Self> {
+        let way_index = file.way_indexes.get(&m.way_id).copied()?;
+        let node_ids = file.ways[way_index].node_ids();
+        let a = *graph.vertices.get(node_ids.get(m.segment_index)?)?;
+        let b = *graph.vertices.get(node_ids.get(m.segment_index + 1)?)?;
+        let way = way_index as u32;
+
+        let pass = |from: u32, to: u32| {
+            let edge = graph.find_edge(from, to, way)?;
+            Some(Pass {
+                weight: edge.weight,
+                distance_m: edge.distance_m,
+                entry: edge.target,
+                exits: graph
+                    .vertices_of(from)
+                    .filter(|&v| graph.find_edge(v, to, way).is_some())
+                    .collect(),
+            })
+        };
+
+        Some(Snap {
+            point: [m.lat, m.lon],
+            way,
+            segment: m.segment_index,
+            fraction: m.fraction,
+            forward: pass(a, b),
+            backward: pass(b, a),
+        })
+    }
+
+    /// Vertices reachable from the snapped point, with the cost of getting there.
+    pub fn entries(&self) -> Vec<Leg> {
+        let mut legs = vec![];
+        if let Some(pass) = &self.forward {
+            legs.push(leg(pass, pass.entry, 1.0 - self.fraction));
+        }
+        if let Some(pass) = &self.backward {
+            legs.push(leg(pass, pass.entry, self.fraction));
+        }
+        legs
+    }
+
+    /// Vertices the snapped point can be reached from, with the cost of the remaining way.
+    pub fn exits(&self) -> Vec<Leg> {
+        let mut legs = vec![];
+        if let Some(pass) = &self.forward {
+            legs.extend(pass.exits.iter().map(|&v| leg(pass, v, self.fraction)));
+        }
+        if let Some(pass) = &self.backward {
+            legs.extend(
+                pass.exits
+                    .iter()
+                    .map(|&v| leg(pass, v, 1.0 - self.fraction)),
+            );
+        }
+        legs
+    }
+
+    /// The cost of travelling directly from `self` to `other` when both lie on the same
+    /// segment, without passing through a vertex.
+    pub fn direct_to(&self, other: &Snap) -> Option<(f64, f64)> {
+        if self.way != other.way || self.segment != other.segment {
+            return None;
+        }
+
+        let t = other.fraction - self.fraction;
+        let pass = if t >= 0.0 {
+            self.forward.as_ref()?
+        } else {
+            self.backward.as_ref()?
+        };
+        Some((t.abs() * pass.weight, t.abs() * pass.distance_m))
+    }
+}
+
+fn leg(pass: &Pass, vertex: u32, share: f64) -> Leg {
+    Leg {
+        vertex,
+        weight: share * pass.weight,
+        distance_m: share * pass.distance_m,
+    }
+}
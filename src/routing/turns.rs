@@ -0,0 +1,394 @@
+//! Turn restrictions, applied by expanding the graph.
+//!
+//! Every restriction becomes a sequence of edges: a `no_*` restriction forbids travelling its
+//! whole sequence, an `only_*` restriction allows nothing but its `to` edges after the rest of
+//! its sequence. The sequences form a trie with Aho-Corasick failure links, so that each trie
+//! state is the longest restriction prefix the edges travelled so far end with.
+//!
+//! Each state becomes a copy of the vertex its last edge leads to, and edges are redirected so
+//! that a search arrives at the copy exactly when it has just travelled the state's edges. The
+//! copy lacks the edges that would complete a forbidden sequence. Searches then need no
+//! knowledge of restrictions, and contraction hierarchies are built on the expanded graph.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::graph::{Edge, Graph};
+use crate::{RestrictionKind, TOSMFile, TurnRestriction, Via};
+
+const ROOT: usize = 0;
+
+#[derive(Debug, Default)]
+struct State {
+    children: HashMap<u32, usize>,
+    fail: usize,
+    /// Whether reaching the state completes a `no_*` sequence.
+    forbidden: bool,
+    /// When set, the only edges that may follow.
+    only: Option<Vec<u32>>,
+    /// The vertex the state's last edge leads to.
+    vertex: u32,
+}
+
+struct Trie {
+    states: Vec<State>,
+}
+
+impl Trie {
+    fn insert(&mut self, graph: &Graph, edges: &[u32]) -> usize {
+        let mut s = ROOT;
+        for &e in edges {
+            s = match self.states[s].children.get(&e) {
+                Some(&child) => child,
+                None => {
+                    let child = self.states.len();
+                    self.states.push(State {
+                        vertex: graph.edges[e as usize].target,
+                        ..State::default()
+                    });
+                    self.states[s].children.insert(e, child);
+                    child
+                }
+            };
+        }
+        s
+    }
+
+    /// The state after travelling edge `e` in state `s`.
+    fn next(&self, mut s: usize, e: u32) -> usize {
+        loop {
+            if let Some(&child) = self.states[s].children.get(&e) {
+                return child;
+            }
+            if s == ROOT {
+                return ROOT;
+            }
+            s = self.states[s].fail;
+        }
+    }
+
+    /// Sets the failure links and passes the constraints of each state on to the states that
+    /// end with it.
+    fn link(&mut self) {
+        let mut queue: VecDeque<usize> = VecDeque::from([ROOT]);
+        while let Some(s) = queue.pop_front() {
+            let mut children: Vec<(u32, usize)> = self.states[s]
+                .children
+                .iter()
+                .map(|(&e, &c)| (e, c))
+                .collect();
+            children.sort();
+
+            for (e, child) in children {
+                let fail = if s == ROOT {
+                    ROOT
+                } else {
+                    self.next(self.states[s].fail, e)
+                };
+                let forbidden = self.states[s].forbidden || self.states[fail].forbidden;
+                let only = intersect(&self.states[child].only, &self.states[fail].only);
+
+                let state = &mut self.states[child];
+                state.fail = fail;
+                state.forbidden |= forbidden;
+                state.only = only;
+                queue.push_back(child);
+            }
+        }
+    }
+}
+
+fn intersect(a: &Option<Vec<u32>>, b: &Option<Vec<u32>>) -> Option<Vec<u32>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.iter().copied().filter(|e| b.contains(e)).collect()),
+        (a, b) => a.clone().or_else(|| b.clone()),
+    }
+}
+
+/// Expands `graph` with the file's turn restrictions for its profile.
+pub(crate) fn restrict(graph: &mut Graph, file: &TOSMFile) {
+    let mut trie = Trie {
+        states: vec![State::default()],
+    };
+    for restriction in file.turn_restrictions() {
+        if !restriction.applies_to(graph.profile) {
+            continue;
+        }
+        let turns = match resolve(graph, file, restriction) {
+            Some(turns) => turns,
+            None => continue,
+        };
+
+        for (prefix, to_edges) in turns {
+            if to_edges.is_empty() {
+                continue;
+            }
+            match restriction.kind() {
+                RestrictionKind::No => {
+                    for t in to_edges {
+                        let mut edges = prefix.clone();
+                        edges.push(t);
+                        let s = trie.insert(graph, &edges);
+                        trie.states[s].forbidden = true;
+                    }
+                }
+                RestrictionKind::Only => {
+                    // Several `only_*` restrictions after the same edges each allow their turn.
+                    let s = trie.insert(graph, &prefix);
+                    let only = trie.states[s].only.get_or_insert_with(Vec::new);
+                    for e in to_edges {
+                        if !only.contains(&e) {
+                            only.push(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if trie.states.len() == 1 {
+        return;
+    }
+    trie.link();
+
+    let n = graph.len() as u32;
+    let mut state_vertex = vec![None; trie.states.len()];
+    for (s, state) in trie.states.iter().enumerate().skip(1) {
+        if !state.forbidden {
+            state_vertex[s] = Some(graph.add_copy(state.vertex));
+        }
+    }
+
+    let mut arcs: Vec<(u32, Edge)> = Vec::with_capacity(graph.edges.len());
+    let mut expand = |source: u32, v: u32, s: usize| {
+        let state = &trie.states[s];
+        for (e, edge) in graph.edges_from(v) {
+            if state.only.as_ref().is_some_and(|only| !only.contains(&e)) {
+                continue;
+            }
+            let next = trie.next(s, e);
+            let target = if next == ROOT {
+                edge.target
+            } else {
+                match state_vertex[next] {
+                    Some(copy) => copy,
+                    None => continue,
+                }
+            };
+            arcs.push((source, Edge { target, ..*edge }));
+        }
+    };
+    for v in 0..n {
+        expand(v, v, ROOT);
+    }
+    for (s, state) in trie.states.iter().enumerate().skip(1) {
+        if let Some(copy) = state_vertex[s] {
+            expand(copy, state.vertex, s);
+        }
+    }
+    graph.set_edges(arcs);
+}
+
+/// The restriction's edge sequences up to the turn, each with the `to` edges that may follow.
+/// `None` when the restriction doesn't fit the graph, e.g. because a way is missing or can't
+/// be travelled in the needed direction.
+fn resolve(
+    graph: &Graph,
+    file: &TOSMFile,
+    restriction: &TurnRestriction,
+) -> Option<Vec<(Vec<u32>, Vec<u32>)>> {
+    let way_index = |id: u64| file.way_indexes.get(&id).map(|&i| i as u32);
+    let from_way = way_index(restriction.from_way())?;
+    let to_way = way_index(restriction.to_way())?;
+
+    let (start, via_edges, end) = match restriction.via() {
+        Via::Node(node) => (*node, vec![], *node),
+        Via::Ways(ways) => via_ways(graph, file, from_way, ways)?,
+    };
+
+    let mut turns = vec![];
+    for from in edges_into(graph, file, from_way, start) {
+        let mut to: Vec<u32> = edges_out_of(graph, to_way, end).collect();
+        if from_way == to_way && via_edges.is_empty() {
+            // A way passing through the via node can be left ahead or back the way it came.
+            // `no_*` restrictions along a single way are U-turn bans, `only_*` ones mandate
+            // going straight on.
+            let back = graph.edge_source(from);
+            let u_turn = restriction.kind() == RestrictionKind::No;
+            to.retain(|&t| (graph.edges[t as usize].target == back) == u_turn);
+        }
+
+        let mut prefix = vec![from];
+        prefix.extend(&via_edges);
+        turns.push((prefix, to));
+    }
+    Some(turns)
+}
+
+/// Follows the via ways from a node of `from_way`, each from one end to the other. Returns the
+/// first and last node and the edges travelled.
+fn via_ways(
+    graph: &Graph,
+    file: &TOSMFile,
+    from_way: u32,
+    ways: &[u64],
+) -> Option<(u64, Vec<u32>, u64)> {
+    let nodes = |id: u64| -> Option<(u32, &[u64])> {
+        let index = *file.way_indexes.get(&id)?;
+        Some((index as u32, file.ways()[index].node_ids()))
+    };
+    let (_, first) = nodes(*ways.first()?)?;
+    let from_nodes = file.ways()[from_way as usize].node_ids();
+
+    'start: for start in [*first.first()?, *first.last()?] {
+        if !from_nodes.contains(&start) {
+            continue;
+        }
+
+        let mut node = start;
+        let mut edges = vec![];
+        for &id in ways {
+            let (way, way_nodes) = nodes(id)?;
+            let mut order: Vec<u64> = way_nodes.to_vec();
+            if order.first() != Some(&node) {
+                order.reverse();
+            }
+            if order.first() != Some(&node) {
+                continue 'start;
+            }
+            order.dedup();
+
+            for pair in order.windows(2) {
+                match edge_between(graph, pair[0], pair[1], way) {
+                    Some(e) => edges.push(e),
+                    None => continue 'start,
+                }
+            }
+            node = *order.last()?;
+        }
+        return Some((start, edges, node));
+    }
+    None
+}
+
+/// The edges along `way` arriving at `node`.
+fn edges_into(graph: &Graph, file: &TOSMFile, way: u32, node: u64) -> Vec<u32> {
+    let node_ids = file.ways()[way as usize].node_ids();
+    let mut edges = vec![];
+    for (i, &id) in node_ids.iter().enumerate() {
+        if id != node {
+            continue;
+        }
+        let neighbours = [i.checked_sub(1), Some(i + 1)];
+        for &j in neighbours.iter().flatten() {
+            if let Some(e) = node_ids
+                .get(j)
+                .and_then(|&from| edge_between(graph, from, node, way))
+            {
+                if !edges.contains(&e) {
+                    edges.push(e);
+                }
+            }
+        }
+    }
+    edges
+}
+
+/// The edges along `way` leaving `node`.
+fn edges_out_of(graph: &Graph, way: u32, node: u64) -> impl Iterator<Item = u32> + '_ {
+    let v = graph.vertices.get(&node).copied();
+    v.into_iter().flat_map(move |v| {
+        graph
+            .edges_from(v)
+            .filter(move |(_, edge)| edge.way == way)
+            .map(|(e, _)| e)
+    })
+}
+
+fn edge_between(graph: &Graph, from: u64, to: u64, way: u32) -> Option<u32> {
+    let (&a, &b) = (graph.vertices.get(&from)?, graph.vertices.get(&to)?);
+    graph
+        .edges_from(a)
+        .find(|(_, edge)| edge.target == b && edge.way == way)
+        .map(|(e, _)| e)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::tests::grid_with_relations;
+    use crate::{Profile, Router};
+
+    #[test]
+    fn obeys_turn_restrictions() {
+        // From halfway along Neðri (10) to just north of node 2 on Mið (14).
+        let (from, to) = ((64.0, -20.999), (64.0002, -20.998));
+        let restricted = [
+            // No left turn from Neðri into Mið.
+            r#"{"type": "restriction", "restriction": "no_left_turn"},
+               "members": [{"type": "way", "ref": 10, "role": "from"},
+                           {"type": "node", "ref": 2, "role": "via"},
+                           {"type": "way", "ref": 14, "role": "to"}]"#,
+            // Straight on along Neðri only.
+            r#"{"type": "restriction", "restriction": "only_straight_on"},
+               "members": [{"type": "way", "ref": 10, "role": "from"},
+                           {"type": "node", "ref": 2, "role": "via"},
+                           {"type": "way", "ref": 11, "role": "to"}]"#,
+        ];
+        let relation =
+            |tags_and_members: &str| format!(r#"[{{"id": 30, "tags": {}}}]"#, tags_and_members);
+
+        let file = grid_with_relations("[]");
+        let route = Router::new(&file)
+            .route_between(from.0, from.1, to.0, to.1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(route.node_ids, vec![2]);
+
+        for restriction in restricted {
+            let mut file = grid_with_relations(&relation(restriction));
+            assert_eq!(file.turn_restrictions().len(), 1);
+
+            for with_ch in [false, true] {
+                if with_ch {
+                    file.build_contraction_hierarchy(Profile::Car);
+                }
+                let router = Router::new(&file);
+                let route = router
+                    .route_between(from.0, from.1, to.0, to.1)
+                    .unwrap()
+                    .unwrap();
+                // Turning around at node 3 is the quickest way back onto Mið.
+                assert_eq!(route.node_ids, vec![2, 3, 2], "{}", restriction);
+                assert_eq!(route.way_ids, vec![10, 11, 14]);
+
+                let matrix = router.distance_matrix(&[from], &[to]).unwrap();
+                assert!((matrix.distances_m[0][0].unwrap() - route.distance_m).abs() < 1e-6);
+            }
+
+            // Pedestrians may turn anyway.
+            let route = Router::with_profile(&file, Profile::Foot)
+                .route_between(from.0, from.1, to.0, to.1)
+                .unwrap()
+                .unwrap();
+            assert_eq!(route.node_ids, vec![2]);
+        }
+
+        // Down Vestur (13), along Neðri (10) and up Mið (14) is banned; driving around Efri
+        // (12) is allowed.
+        let via_way = relation(
+            r#"{"type": "restriction", "restriction": "no_u_turn", "except": "bicycle"},
+               "members": [{"type": "way", "ref": 13, "role": "from"},
+                           {"type": "way", "ref": 10, "role": "via"},
+                           {"type": "way", "ref": 14, "role": "to"}]"#,
+        );
+        let file = grid_with_relations(&via_way);
+        let from = (64.0005, -21.0);
+        for (profile, nodes) in [(Profile::Car, vec![4, 5]), (Profile::Bicycle, vec![1, 2])] {
+            let route = Router::with_profile(&file, profile)
+                .route_between(from.0, from.1, to.0, to.1)
+                .unwrap()
+                .unwrap();
+            assert_eq!(route.node_ids, nodes, "{:?}", profile);
+        }
+        let route = Router::new(&file).route(4, 5).unwrap().unwrap();
+        assert_eq!(route.node_ids, vec![4, 5]);
+    }
+}
@@ -0,0 +1,390 @@
+//! Search of street names, for forward geocoding and autocompletion.
+//!
+//! Names are compared in a normalized form: lowercase, with accents and ligatures folded to
+//! plain Latin letters (`Þingholtsstræti` becomes `thingholtsstraeti`) and punctuation turned
+//! into single spaces. A query matches a name when it is a prefix of the name or of one of its
+//! words, allowing a few typos in longer queries. Ways with the same name that share a node
+//! form one street.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::geo::{distance_m, unwrap_lons};
+use crate::{TOSMFile, Way};
+
+/// A street found by [`TOSMFile::search_streets`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreetMatch<'a> {
+    pub name: &'a str,
+    /// The connected ways that make up the street.
+    pub ways: Vec<&'a Way>,
+    /// `[lat, lon]` of the length-weighted centre of the street's segments.
+    pub centroid: [f64; 2],
+    /// Number of typos corrected to match the query.
+    pub edits: u32,
+}
+
+/// Streets grouped from the named ways of a file, with their normalized names.
+#[derive(Debug, Default)]
+pub(crate) struct StreetIndex {
+    streets: Vec<Street>,
+    /// The normalized name of a street from each of its word starts on, sorted, with the
+    /// index of the street.
+    entries: Vec<(String, u32)>,
+}
+
+#[derive(Debug)]
+struct Street {
+    /// Indexes of the ways, the first of which gives the displayed name.
+    ways: Vec<usize>,
+    centroid: [f64; 2],
+    /// Length of the normalized name, to tell entries at the name start from later words.
+    key_len: usize,
+}
+
+impl StreetIndex {
+    pub fn new(file: &TOSMFile) -> Self {
+        // Ordered by name, so that streets, and the order of equally ranked matches, don't
+        // depend on hashing.
+        let mut by_name: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, way) in file.ways.iter().enumerate() {
+            let key = way.name().map(normalize).unwrap_or_default();
+            if !key.is_empty() {
+                by_name.entry(key).or_default().push(i);
+            }
+        }
+
+        let mut index = StreetIndex::default();
+        for (key, ways) in by_name {
+            for ways in connected(file, &ways) {
+                let Some(centroid) = centroid(file, &ways) else {
+                    continue;
+                };
+                let street = index.streets.len() as u32;
+                index.streets.push(Street {
+                    ways,
+                    centroid,
+                    key_len: key.len(),
+                });
+                index.entries.push((key.clone(), street));
+                for (space, _) in key.match_indices(' ') {
+                    index.entries.push((key[space + 1..].to_string(), street));
+                }
+            }
+        }
+        index.entries.sort();
+        index
+    }
+}
+
+impl TOSMFile {
+    /// Finds up to `limit` streets whose name matches `query`, best first: fewer typos
+    /// first, then exact names before prefixes and matches at the start of a name before
+    /// matches at a later word. Queries of 4 to 7 letters may contain one typo, longer ones
+    /// two.
+    pub fn search_streets(&self, query: &str, limit: usize) -> Vec<StreetMatch<'_>> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let letters: Vec<char> = query.chars().collect();
+        let max_edits = match letters.len() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+
+        let index = self.street_index();
+        // Per street, the best (edits, inexact, later word, name length) seen.
+        let mut best: HashMap<u32, (u32, bool, bool, usize)> = HashMap::new();
+        let mut consider = |key: &str, street: u32, edits: u32| {
+            let found = &index.streets[street as usize];
+            let rank = (
+                edits,
+                key != query,
+                key.len() != found.key_len,
+                found.key_len,
+            );
+            let entry = best.entry(street).or_insert(rank);
+            *entry = rank.min(*entry);
+        };
+
+        if max_edits == 0 {
+            let start = index
+                .entries
+                .partition_point(|(key, _)| key.as_str() < query.as_str());
+            for (key, street) in index.entries[start..]
+                .iter()
+                .take_while(|(key, _)| key.starts_with(&query))
+            {
+                consider(key, *street, 0);
+            }
+        } else {
+            for (key, street, edits) in fuzzy_matches(&index.entries, &letters, max_edits) {
+                consider(key, street, edits);
+            }
+        }
+
+        let mut found: Vec<_> = best.into_iter().collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        found
+            .into_iter()
+            .take(limit)
+            .filter_map(|(street, (edits, ..))| {
+                let street = &index.streets[street as usize];
+                Some(StreetMatch {
+                    name: self.ways[street.ways[0]].name()?,
+                    ways: street.ways.iter().map(|&i| &self.ways[i]).collect(),
+                    centroid: street.centroid,
+                    edits,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Lowercases `s`, folds accented letters and ligatures to plain Latin letters and replaces
+/// runs of other characters with a single space.
+pub(crate) fn normalize(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        let folded = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'æ' => "ae",
+            'ç' | 'ć' | 'č' => "c",
+            'ð' | 'ď' | 'đ' => "d",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'ğ' => "g",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+            'ł' => "l",
+            'ñ' | 'ń' | 'ň' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+            'œ' => "oe",
+            'ř' => "r",
+            'ś' | 'š' | 'ş' => "s",
+            'ß' => "ss",
+            'ť' | 'ţ' => "t",
+            'þ' => "th",
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+            'ý' | 'ÿ' => "y",
+            'ź' | 'ż' | 'ž' => "z",
+            c if c.is_alphanumeric() => {
+                out.push(c);
+                continue;
+            }
+            _ => " ",
+        };
+        if folded != " " || !(out.is_empty() || out.ends_with(' ')) {
+            out.push_str(folded);
+        }
+    }
+    if out.ends_with(' ') {
+        out.pop();
+    }
+    out
+}
+
+/// The entries with a key that has a prefix at most `max_edits` edits from `query`, with the
+/// fewest edits. The sorted entries are walked like a trie: keys share the edit distance
+/// columns of their common prefix, and once a prefix is too far from the query every key
+/// starting with it is settled at once.
+fn fuzzy_matches<'a>(
+    entries: &'a [(String, u32)],
+    query: &[char],
+    max_edits: u32,
+) -> Vec<(&'a str, u32, u32)> {
+    let n = query.len();
+    // columns[d][i] is the edit distance between the first i query letters and the first d
+    // letters of the current key.
+    let mut columns: Vec<Vec<u32>> = vec![(0..=n as u32).collect()];
+    let mut previous = "";
+    let mut found = vec![];
+    let mut i = 0;
+    while i < entries.len() {
+        let key = entries[i].0.as_str();
+        let shared = previous
+            .chars()
+            .zip(key.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        columns.truncate(shared.min(columns.len() - 1) + 1);
+        previous = key;
+
+        let mut dead_end = None;
+        for (start, k) in key.char_indices().skip(columns.len() - 1) {
+            let last = &columns[columns.len() - 1];
+            let mut column = Vec::with_capacity(n + 1);
+            column.push(last[0] + 1);
+            for q in 1..=n {
+                let substitution = last[q - 1] + u32::from(query[q - 1] != k);
+                column.push(substitution.min(last[q] + 1).min(column[q - 1] + 1));
+            }
+            let dead = column.iter().all(|&d| d > max_edits);
+            columns.push(column);
+            if dead {
+                dead_end = Some(start + k.len_utf8());
+                break;
+            }
+        }
+
+        // Further letters can't lower the distance below that of a dead prefix, so all keys
+        // sharing it match alike.
+        let settled = match dead_end {
+            Some(end) => entries[i..].partition_point(|(other, _)| other.starts_with(&key[..end])),
+            None => 1,
+        };
+        let edits = columns
+            .iter()
+            .map(|column| column[n])
+            .min()
+            .unwrap_or(u32::MAX);
+        if edits <= max_edits {
+            found.extend(
+                entries[i..i + settled]
+                    .iter()
+                    .map(|(key, street)| (key.as_str(), *street, edits)),
+            );
+        }
+        i += settled;
+    }
+    found
+}
+
+/// Splits ways into groups connected through shared nodes.
+fn connected(file: &TOSMFile, ways: &[usize]) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..ways.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut first_way: HashMap<u64, usize> = HashMap::new();
+    for (i, &way) in ways.iter().enumerate() {
+        for &node in file.ways[way].node_ids() {
+            let other = *first_way.entry(node).or_insert(i);
+            let (a, b) = (root(&mut parent, i), root(&mut parent, other));
+            parent[a] = b;
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, &way) in ways.iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(way);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort();
+    groups
+}
+
+/// The length-weighted centre of the segments of `ways`, or of their nodes when all
+/// segments have zero length.
+fn centroid(file: &TOSMFile, ways: &[usize]) -> Option<[f64; 2]> {
+    let (mut sum, mut total) = ([0.0, 0.0], 0.0);
+    let (mut node_sum, mut nodes) = ([0.0, 0.0], 0.0);
+    let mut origin: Option<f64> = None;
+    for &way in ways {
+        let coords = unwrap_lons(&file.way_coords(&file.ways[way]));
+        // Keep every way on the same side of the antimeridian as the first.
+        let shift = match (origin, coords.first()) {
+            (Some(lon), Some(c)) => ((lon - c[1]) / 360.0).round() * 360.0,
+            (None, Some(c)) => {
+                origin = Some(c[1]);
+                0.0
+            }
+            (_, None) => continue,
+        };
+        for pair in coords.windows(2) {
+            let length = distance_m(pair[0], pair[1]);
+            sum[0] += length * (pair[0][0] + pair[1][0]) / 2.0;
+            sum[1] += length * ((pair[0][1] + pair[1][1]) / 2.0 + shift);
+            total += length;
+        }
+        for c in &coords {
+            node_sum[0] += c[0];
+            node_sum[1] += c[1] + shift;
+            nodes += 1.0;
+        }
+    }
+
+    let [lat, lon] = if total > 0.0 {
+        [sum[0] / total, sum[1] / total]
+    } else if nodes > 0.0 {
+        [node_sum[0] / nodes, node_sum[1] / nodes]
+    } else {
+        return None;
+    };
+    Some([lat, (lon + 540.0).rem_euclid(360.0) - 180.0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+    use crate::TOSMFile;
+
+    #[test]
+    fn finds_streets_by_folded_prefix_and_typos() {
+        assert_eq!(normalize(" Þingholts-STRÆTI "), "thingholts straeti");
+
+        // Fjólugata in two places, one of them split into two ways, and a Hringbraut that
+        // also appears as the second word of another name.
+        let source = r#"{
+            "nodes": [
+                {"id": 1, "lat": 64.1420, "lon": -21.9390},
+                {"id": 2, "lat": 64.1420, "lon": -21.9380},
+                {"id": 3, "lat": 64.1420, "lon": -21.9370},
+                {"id": 4, "lat": 65.6800, "lon": -18.1000},
+                {"id": 5, "lat": 65.6810, "lon": -18.1000},
+                {"id": 6, "lat": 64.1450, "lon": -21.9500},
+                {"id": 7, "lat": 64.1450, "lon": -21.9400},
+                {"id": 8, "lat": 64.1460, "lon": -21.9500},
+                {"id": 9, "lat": 64.1460, "lon": -21.9400}
+            ],
+            "ways": [
+                {"id": 10, "node_ids": [1, 2], "one_way": false, "name": "Fjólugata"},
+                {"id": 11, "node_ids": [2, 3], "one_way": false, "name": "Fjólugata"},
+                {"id": 12, "node_ids": [4, 5], "one_way": false, "name": "Fjólugata"},
+                {"id": 13, "node_ids": [6, 7], "one_way": false, "name": "Gamla Hringbraut"},
+                {"id": 14, "node_ids": [8, 9], "one_way": false, "name": "Hringbraut"},
+                {"id": 15, "node_ids": [6, 8], "one_way": false, "name": "Miðtún"},
+                {"id": 16, "node_ids": [7, 9], "one_way": false, "name": "Miðhús"}
+            ]
+        }"#;
+        let file = TOSMFile::from_json_str(source).unwrap();
+
+        let found = file.search_streets("Fjolugata", 10);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].name, "Fjólugata");
+        assert_eq!(found[0].edits, 0);
+        let ids: Vec<u64> = found[0].ways.iter().map(|w| w.id()).collect();
+        assert_eq!(ids, [10, 11]);
+        assert!((found[0].centroid[0] - 64.1420).abs() < 1e-9);
+        assert!((found[0].centroid[1] + 21.9380).abs() < 1e-9);
+
+        assert_eq!(file.search_streets("fjó", 10).len(), 2);
+        assert_eq!(file.search_streets("fjólugata", 1).len(), 1);
+
+        let typo = file.search_streets("fjolgata", 10);
+        assert_eq!(typo.len(), 2);
+        assert_eq!(typo[0].edits, 1);
+
+        let names: Vec<&str> = file
+            .search_streets("hring", 10)
+            .iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, ["Hringbraut", "Gamla Hringbraut"]);
+
+        // Equally ranked streets come in name order.
+        let names: Vec<&str> = file
+            .search_streets("mið", 10)
+            .iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(names, ["Miðhús", "Miðtún"]);
+
+        assert!(file.search_streets("braut", 10).is_empty());
+        assert!(file.search_streets("  ", 10).is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Compact storage of the OSM tags of nodes, ways and relations.
+//!
+//! Keys and values are interned once per file, and identical tag sets, such as the same
+//! `highway=residential` + `surface=asphalt` on many ways, are stored once. Untagged elements
+//! take no space at all.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Settings for importing source data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportOptions {
+    /// When set, only tags whose key matches one of these patterns are kept. A pattern ending
+    /// in `*` matches by prefix (`addr:*`), any other pattern matches the whole key. Routing is
+    /// not affected, as ways keep the attributes it needs.
+    pub include_tags: Option<Vec<String>>,
+    /// Tags whose key matches one of these patterns are dropped, even if included.
+    pub exclude_tags: Vec<String>,
+    /// What to do with ways that refer to nodes missing from the source.
+    pub missing_nodes: MissingNodes,
+}
+
+/// How imports treat ways that refer to nodes missing from the source, as clipped extracts
+/// do for ways crossing their border.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingNodes {
+    /// Fail with [`TosmError::DanglingNodeRef`](crate::TosmError::DanglingNodeRef).
+    #[default]
+    Reject,
+    /// Leave out ways with any missing node.
+    DropWays,
+    /// Keep the longest run of consecutive nodes that exist, leaving out ways with fewer than
+    /// two.
+    Truncate,
+}
+
+impl ImportOptions {
+    pub(crate) fn keeps_tag(&self, key: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == pattern,
+        };
+
+        self.include_tags
+            .as_ref()
+            .is_none_or(|include| include.iter().any(matches))
+            && !self.exclude_tags.iter().any(matches)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TagStore {
+    strings: Vec<String>,
+    /// Key and value string indexes of all tag sets, one set after another. Set `s` is
+    /// `pairs[set_first[s]..set_first[s + 1]]`.
+    pairs: Vec<[u32; 2]>,
+    set_first: Vec<u32>,
+    nodes: HashMap<u64, u32>,
+    ways: HashMap<u64, u32>,
+    relations: HashMap<u64, u32>,
+
+    /// Lookups for interning while importing.
+    #[serde(skip)]
+    string_ids: HashMap<String, u32>,
+    #[serde(skip)]
+    set_ids: HashMap<Vec<[u32; 2]>, u32>,
+}
+
+impl Default for TagStore {
+    fn default() -> Self {
+        TagStore {
+            strings: vec![],
+            pairs: vec![],
+            set_first: vec![0],
+            nodes: HashMap::new(),
+            ways: HashMap::new(),
+            relations: HashMap::new(),
+            string_ids: HashMap::new(),
+            set_ids: HashMap::new(),
+        }
+    }
+}
+
+impl TagStore {
+    /// Replaces the tags of a node with those `options` keeps.
+    pub fn set_node<'a, I>(&mut self, id: u64, tags: I, options: &ImportOptions)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let set = self.intern_set(tags, options);
+        assign(&mut self.nodes, id, set);
+    }
+
+    /// Replaces the tags of a way with those `options` keeps.
+    pub fn set_way<'a, I>(&mut self, id: u64, tags: I, options: &ImportOptions)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let set = self.intern_set(tags, options);
+        assign(&mut self.ways, id, set);
+    }
+
+    /// Replaces the tags of a relation with those `options` keeps.
+    pub fn set_relation<'a, I>(&mut self, id: u64, tags: I, options: &ImportOptions)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let set = self.intern_set(tags, options);
+        assign(&mut self.relations, id, set);
+    }
+
+    pub fn node(&self, id: u64) -> Tags<'_> {
+        self.tags(self.nodes.get(&id))
+    }
+
+    pub fn way(&self, id: u64) -> Tags<'_> {
+        self.tags(self.ways.get(&id))
+    }
+
+    pub fn relation(&self, id: u64) -> Tags<'_> {
+        self.tags(self.relations.get(&id))
+    }
+
+    /// The nodes that kept tags, with their tags, in no particular order.
+    pub fn tagged_nodes(&self) -> impl Iterator<Item = (u64, Tags<'_>)> {
+        self.nodes
+            .iter()
+            .map(|(&id, set)| (id, self.tags(Some(set))))
+    }
+
+    /// The ways that kept tags, with their tags, in no particular order.
+    pub fn tagged_ways(&self) -> impl Iterator<Item = (u64, Tags<'_>)> {
+        self.ways
+            .iter()
+            .map(|(&id, set)| (id, self.tags(Some(set))))
+    }
+
+    fn tags(&self, set: Option<&u32>) -> Tags<'_> {
+        let pairs = match set {
+            Some(&s) => {
+                &self.pairs
+                    [self.set_first[s as usize] as usize..self.set_first[s as usize + 1] as usize]
+            }
+            None => &[],
+        };
+        Tags {
+            strings: &self.strings,
+            pairs,
+        }
+    }
+
+    /// Interns a tag set, returning `None` when no tag is kept.
+    fn intern_set<'a, I>(&mut self, tags: I, options: &ImportOptions) -> Option<u32>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let pairs: Vec<[u32; 2]> = tags
+            .into_iter()
+            .filter(|(key, _)| options.keeps_tag(key))
+            .map(|(key, value)| [self.intern(key), self.intern(value)])
+            .collect();
+        if pairs.is_empty() {
+            return None;
+        }
+
+        if let Some(&set) = self.set_ids.get(&pairs) {
+            return Some(set);
+        }
+        let set = self.set_first.len() as u32 - 1;
+        self.pairs.extend(&pairs);
+        self.set_first.push(self.pairs.len() as u32);
+        self.set_ids.insert(pairs, set);
+        Some(set)
+    }
+
+    fn intern(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+}
+
+fn assign(sets: &mut HashMap<u64, u32>, id: u64, set: Option<u32>) {
+    match set {
+        Some(set) => sets.insert(id, set),
+        None => sets.remove(&id),
+    };
+}
+
+/// The tags of a node, way or relation, in source order.
+#[derive(Debug, Clone, Copy)]
+pub struct Tags<'a> {
+    strings: &'a [String],
+    pairs: &'a [[u32; 2]],
+}
+
+impl<'a> Tags<'a> {
+    /// The value of `key`, if the element has it.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let strings = self.strings;
+        self.pairs
+            .iter()
+            .map(move |[k, v]| (strings[*k as usize].as_str(), strings[*v as usize].as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportOptions, TagStore};
+
+    #[test]
+    fn interns_and_filters_tags() {
+        let options = ImportOptions {
+            include_tags: Some(vec!["highway".into(), "addr:*".into(), "name".into()]),
+            exclude_tags: vec!["addr:postcode".into()],
+            ..ImportOptions::default()
+        };
+        let mut store = TagStore::default();
+
+        let road = [("highway", "residential"), ("name", "Fjólugata")];
+        store.set_way(1, road, &options);
+        store.set_way(2, road, &options);
+        store.set_way(3, [("source", "survey")], &options);
+        store.set_node(
+            4,
+            [
+                ("addr:street", "Fjólugata"),
+                ("addr:housenumber", "5"),
+                ("addr:postcode", "101"),
+            ],
+            &options,
+        );
+
+        assert_eq!(store.way(1).get("name"), Some("Fjólugata"));
+        assert_eq!(store.way(2).iter().collect::<Vec<_>>(), road);
+        assert_eq!(store.set_first.len(), 3);
+        assert_eq!(store.strings.len(), 7);
+
+        assert!(store.way(3).is_empty());
+        assert!(store.node(1).is_empty());
+        assert_eq!(store.node(4).len(), 2);
+        assert_eq!(store.node(4).get("addr:postcode"), None);
+    }
+}
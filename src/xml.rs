@@ -0,0 +1,476 @@
+//! Streaming reader for OSM XML (`.osm`) and osmChange (`.osc`) documents.
+//!
+//! Objects inside an osmChange `<delete>` block, and objects JOSM marked with
+//! `action="delete"`, are dropped, together with any earlier version of them in the same
+//! document. When the same id appears more than once the last
+//! occurrence wins, so applying a `<modify>` after a `<create>` keeps the modified version.
+//! The replication timestamp of the root element becomes the source timestamp.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::osm::{restrictions_from_tags, way_from_tags};
+use crate::tags::TagStore;
+use crate::{ImportOptions, Member, MemberType, Node, Relation, TosmError, TurnRestriction, Way};
+
+pub(crate) struct XmlContents {
+    pub nodes: Vec<Node>,
+    pub ways: Vec<Way>,
+    pub relations: Vec<Relation>,
+    pub restrictions: Vec<TurnRestriction>,
+    pub tags: TagStore,
+    /// The `osmosis_replication_timestamp` or `timestamp` of the root element, in seconds
+    /// since the Unix epoch.
+    pub timestamp: Option<i64>,
+}
+
+enum Element {
+    Node(Node),
+    Way {
+        id: u64,
+        node_ids: Vec<u64>,
+    },
+    Relation {
+        id: u64,
+        members: Vec<Member>,
+    },
+    /// An element to delete, which needs no more than its id.
+    Deleted(MemberType, u64),
+}
+
+struct Current {
+    element: Element,
+    tags: Vec<(String, String)>,
+}
+
+struct Collector<'o> {
+    options: &'o ImportOptions,
+    tags: TagStore,
+    nodes: Slots<Node>,
+    ways: Slots<Way>,
+    /// Relations with the turn restrictions read from them.
+    relations: Slots<(Relation, Vec<TurnRestriction>)>,
+}
+
+impl Collector<'_> {
+    fn finish(&mut self, current: Current) {
+        let tags = current.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+        match current.element {
+            Element::Node(node) => {
+                self.tags.set_node(node.id(), tags, self.options);
+                self.nodes.upsert(node.id(), node)
+            }
+            Element::Way { id, node_ids } => {
+                self.tags.set_way(id, tags.clone(), self.options);
+                self.ways.upsert(id, way_from_tags(id, node_ids, tags))
+            }
+            Element::Relation { id, members } => {
+                self.tags.set_relation(id, tags.clone(), self.options);
+                let relation = Relation::new(id, members);
+                let restrictions = restrictions_from_tags(&relation, tags);
+                self.relations.upsert(id, (relation, restrictions))
+            }
+            Element::Deleted(member_type, id) => self.delete(member_type, id),
+        }
+    }
+
+    /// Drops an element created or modified earlier in the document, with its tags and
+    /// restrictions.
+    fn delete(&mut self, member_type: MemberType, id: u64) {
+        let none = std::iter::empty::<(&str, &str)>;
+        match member_type {
+            MemberType::Node => {
+                self.tags.set_node(id, none(), self.options);
+                self.nodes.remove(id);
+            }
+            MemberType::Way => {
+                self.tags.set_way(id, none(), self.options);
+                self.ways.remove(id);
+            }
+            MemberType::Relation => {
+                self.tags.set_relation(id, none(), self.options);
+                self.relations.remove(id);
+            }
+        }
+    }
+}
+
+/// Elements in document order by id. Deleting one leaves an empty slot, so that a change with
+/// many deletes stays linear; the slots are dropped once at the end.
+struct Slots<T> {
+    items: Vec<Option<T>>,
+    positions: HashMap<u64, usize>,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Slots {
+            items: vec![],
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Slots<T> {
+    /// Replaces the item with `id` in place, or appends it.
+    fn upsert(&mut self, id: u64, item: T) {
+        match self.positions.get(&id) {
+            Some(&i) => self.items[i] = Some(item),
+            None => {
+                self.positions.insert(id, self.items.len());
+                self.items.push(Some(item));
+            }
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(i) = self.positions.remove(&id) {
+            self.items[i] = None;
+        }
+    }
+
+    fn into_items(self) -> impl Iterator<Item = T> {
+        self.items.into_iter().flatten()
+    }
+}
+
+pub(crate) fn read_xml<R: BufRead>(
+    reader: R,
+    options: &ImportOptions,
+) -> Result<XmlContents, TosmError> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = vec![];
+
+    let mut collector = Collector {
+        options,
+        tags: TagStore::default(),
+        nodes: Slots::default(),
+        ways: Slots::default(),
+        relations: Slots::default(),
+    };
+    let mut timestamp = None;
+    let mut current: Option<Current> = None;
+    let mut in_delete = false;
+
+    loop {
+        let (element, empty) = match reader.read_event(&mut buf).map_err(invalid)? {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.name() {
+                    b"node" | b"way" | b"relation" => {
+                        if let Some(current) = current.take() {
+                            collector.finish(current);
+                        }
+                    }
+                    b"delete" => in_delete = false,
+                    _ => {}
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+
+        let attrs = attributes(&reader, &element)?;
+        match element.name() {
+            b"osm" | b"osmChange" => {
+                let value = attrs
+                    .get("osmosis_replication_timestamp")
+                    .or_else(|| attrs.get("timestamp"));
+                if let Some(value) = value {
+                    timestamp = Some(parse_timestamp(value).ok_or_else(|| {
+                        TosmError::InvalidXml(format!("invalid timestamp {:?}", value))
+                    })?);
+                }
+            }
+            b"node" | b"way" | b"relation" => {
+                let id = parse::<i64>(&attrs, "id")? as u64;
+                let member_type = match element.name() {
+                    b"node" => MemberType::Node,
+                    b"way" => MemberType::Way,
+                    _ => MemberType::Relation,
+                };
+                // Deleted nodes of the OSM API carry no coordinates.
+                let deleted =
+                    in_delete || attrs.get("action").map(String::as_str) == Some("delete");
+                let element = match member_type {
+                    _ if deleted => Element::Deleted(member_type, id),
+                    MemberType::Node => {
+                        Element::Node(Node::new(id, parse(&attrs, "lat")?, parse(&attrs, "lon")?))
+                    }
+                    MemberType::Way => Element::Way {
+                        id,
+                        node_ids: vec![],
+                    },
+                    MemberType::Relation => Element::Relation {
+                        id,
+                        members: vec![],
+                    },
+                };
+                let parsed = Current {
+                    element,
+                    tags: vec![],
+                };
+
+                if empty {
+                    collector.finish(parsed);
+                } else {
+                    current = Some(parsed);
+                }
+            }
+            b"nd" => {
+                if let Some(Current {
+                    element: Element::Way { node_ids, .. },
+                    ..
+                }) = current.as_mut()
+                {
+                    node_ids.push(parse::<i64>(&attrs, "ref")? as u64);
+                }
+            }
+            b"member" => {
+                if let Some(Current {
+                    element: Element::Relation { members, .. },
+                    ..
+                }) = current.as_mut()
+                {
+                    let member_type = match required(&attrs, "type")?.as_str() {
+                        "node" => MemberType::Node,
+                        "way" => MemberType::Way,
+                        "relation" => MemberType::Relation,
+                        other => {
+                            return Err(TosmError::InvalidXml(format!(
+                                "unknown member type {:?}",
+                                other
+                            )))
+                        }
+                    };
+                    let id = parse::<i64>(&attrs, "ref")? as u64;
+                    let role = attrs.get("role").map_or("", String::as_str);
+                    members.push(Member::new(member_type, id, role));
+                }
+            }
+            b"tag" => {
+                if let Some(Current { tags, .. }) = current.as_mut() {
+                    tags.push((
+                        required(&attrs, "k")?.clone(),
+                        required(&attrs, "v")?.clone(),
+                    ));
+                }
+            }
+            b"delete" if !empty => in_delete = true,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    let (relations, restrictions): (Vec<_>, Vec<_>) = collector.relations.into_items().unzip();
+    Ok(XmlContents {
+        nodes: collector.nodes.into_items().collect(),
+        ways: collector.ways.into_items().collect(),
+        relations,
+        restrictions: restrictions.into_iter().flatten().collect(),
+        tags: collector.tags,
+        timestamp,
+    })
+}
+
+/// Seconds since the Unix epoch of a UTC timestamp such as `2022-04-15T05:20:00Z`.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let (date, time) = value.strip_suffix('Z')?.split_once('T')?;
+    let fields = |s: &str, separator| -> Option<Vec<i64>> {
+        let fields: Vec<i64> = s
+            .split(separator)
+            .map(|f| f.parse().ok())
+            .collect::<Option<_>>()?;
+        (fields.len() == 3).then_some(fields)
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let (year, month, day) = (date[0], date[1], date[2]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if !(0..24).contains(&time[0]) || !(0..60).contains(&time[1]) || !(0..=60).contains(&time[2]) {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, counting years from March so
+    // that leap days come last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + time[0] * 3_600 + time[1] * 60 + time[2])
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> TosmError {
+    TosmError::InvalidXml(e.to_string())
+}
+
+fn attributes<R: BufRead>(
+    reader: &Reader<R>,
+    element: &BytesStart,
+) -> Result<HashMap<String, String>, TosmError> {
+    let mut attrs = HashMap::new();
+    for attr in element.attributes() {
+        let attr = attr.map_err(invalid)?;
+        let key = String::from_utf8_lossy(attr.key).into_owned();
+        let value = attr.unescape_and_decode_value(reader).map_err(invalid)?;
+        attrs.insert(key, value);
+    }
+    Ok(attrs)
+}
+
+fn required<'a>(attrs: &'a HashMap<String, String>, key: &str) -> Result<&'a String, TosmError> {
+    attrs
+        .get(key)
+        .ok_or_else(|| TosmError::InvalidXml(format!("missing attribute {}", key)))
+}
+
+fn parse<T: std::str::FromStr>(attrs: &HashMap<String, String>, key: &str) -> Result<T, TosmError> {
+    let value = required(attrs, key)?;
+    value
+        .parse()
+        .map_err(|_| TosmError::InvalidXml(format!("invalid {} attribute {:?}", key, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_timestamp, read_xml};
+    use crate::{ImportOptions, Member, MemberType};
+
+    #[test]
+    fn reads_osm_xml() {
+        let source = r#"<?xml version='1.0' encoding='UTF-8'?>
+            <osm version='0.6' generator='osmium/1.14.0' timestamp='2022-04-15T05:20:00Z'>
+              <node id='-1' lat='64.1420' lon='-21.9380' />
+              <node id='-2' lat='64.1430' lon='-21.9390'>
+                <tag k='highway' v='crossing' />
+              </node>
+              <node id='-3' action='delete' lat='64.1440' lon='-21.9400' />
+              <way id='-10'>
+                <nd ref='-1' />
+                <nd ref='-2' />
+                <tag k='name' v='Fj&#243;lugata' />
+                <tag k='oneway' v='yes' />
+              </way>
+              <relation id='-20'>
+                <member type='way' ref='-10' role='outer' />
+                <member type='node' ref='-2' />
+                <tag k='type' v='multipolygon' />
+              </relation>
+            </osm>"#;
+        let contents = read_xml(source.as_bytes(), &ImportOptions::default()).unwrap();
+
+        assert_eq!(contents.timestamp, Some(1650000000));
+        assert_eq!(contents.nodes.len(), 2);
+        assert_eq!(contents.nodes[0].id(), -1i64 as u64);
+        let crossing = contents.tags.node(-2i64 as u64);
+        assert_eq!(crossing.get("highway"), Some("crossing"));
+        assert_eq!(contents.ways.len(), 1);
+        assert_eq!(contents.ways[0].node_ids(), &[-1i64 as u64, -2i64 as u64]);
+        assert_eq!(contents.ways[0].name(), Some("Fjólugata"));
+        assert!(contents.ways[0].one_way());
+
+        assert_eq!(contents.relations.len(), 1);
+        assert_eq!(
+            contents.relations[0].members(),
+            &[
+                Member::new(MemberType::Way, -10i64 as u64, "outer"),
+                Member::new(MemberType::Node, -2i64 as u64, ""),
+            ]
+        );
+        let tags = contents.tags.relation(-20i64 as u64);
+        assert_eq!(tags.get("type"), Some("multipolygon"));
+    }
+
+    #[test]
+    fn applies_osm_change() {
+        let source = r#"<osmChange version='0.6'>
+              <create>
+                <node id='1' lat='64.0' lon='-21.0'>
+                  <tag k='amenity' v='cafe' />
+                </node>
+                <node id='2' lat='64.1' lon='-21.1'>
+                  <tag k='amenity' v='cafe' />
+                </node>
+              </create>
+              <modify>
+                <node id='1' lat='64.5' lon='-21.5' />
+              </modify>
+              <delete>
+                <node id='3' lat='0' lon='0' />
+                <node id='4' version='2' visible='false' />
+              </delete>
+            </osmChange>"#;
+        let contents = read_xml(source.as_bytes(), &ImportOptions::default()).unwrap();
+
+        assert_eq!(contents.timestamp, None);
+        assert_eq!(contents.nodes.len(), 2);
+        assert_eq!(contents.nodes[0].lat(), 64.5);
+        assert!(contents.tags.node(1).is_empty());
+        assert_eq!(contents.tags.node(2).get("amenity"), Some("cafe"));
+    }
+
+    #[test]
+    fn deletes_elements_created_in_the_same_change() {
+        let source = r#"<osmChange version='0.6'>
+              <create>
+                <node id='1' lat='64.0' lon='-21.0'>
+                  <tag k='amenity' v='cafe' />
+                </node>
+                <node id='2' lat='64.1' lon='-21.1' />
+                <node id='3' lat='64.2' lon='-21.2' />
+                <way id='10'>
+                  <nd ref='1' />
+                  <nd ref='2' />
+                  <tag k='highway' v='residential' />
+                </way>
+                <relation id='20'>
+                  <member type='way' ref='10' role='from' />
+                  <member type='node' ref='2' role='via' />
+                  <member type='way' ref='10' role='to' />
+                  <tag k='type' v='restriction' />
+                  <tag k='restriction' v='no_u_turn' />
+                </relation>
+              </create>
+              <delete>
+                <node id='1' lat='64.0' lon='-21.0' />
+                <way id='10' />
+                <relation id='20' />
+              </delete>
+              <modify>
+                <node id='3' lat='64.3' lon='-21.3' />
+              </modify>
+            </osmChange>"#;
+        let contents = read_xml(source.as_bytes(), &ImportOptions::default()).unwrap();
+
+        let ids: Vec<u64> = contents.nodes.iter().map(|n| n.id()).collect();
+        assert_eq!(ids, [2, 3]);
+        assert_eq!(contents.nodes[1].lat(), 64.3);
+        assert!(contents.tags.node(1).is_empty());
+        assert!(contents.ways.is_empty());
+        assert!(contents.tags.way(10).is_empty());
+        assert!(contents.relations.is_empty());
+        assert!(contents.restrictions.is_empty());
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2000-02-29T12:00:00Z"), Some(951825600));
+        assert_eq!(parse_timestamp("2022-04-15T05:20:00Z"), Some(1650000000));
+        assert_eq!(parse_timestamp("2022-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2022-04-15 05:20:00"), None);
+    }
+}